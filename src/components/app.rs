@@ -1,8 +1,20 @@
-use crate::tui::core::draw_ui;
-use crate::server_talk::session::Session;
+use crate::tui::{
+    bidi,
+    core::{chat_lines, draw_ui, last_message_lines, ChatRows},
+    theme::{ColorDepth, Theme, ThemeConfig},
+};
+use crate::server_talk::{
+    chat::{ChatMessage, ConnState, WsEvent},
+    errors::SessionError,
+    session::{Session, Visibility},
+};
+use crate::settings::{config::Config, keymap::Key, profiles::Profiles};
 use super::{
-    forms::Form, logics, states::{
-        Block, Forms, Modes, Screen
+    events::{self, AppEvent, SessionReply, TICK_RATE},
+    forms::{Form, InputKind}, logics,
+    notices::{Notices, Severity},
+    states::{
+        Block, Confirm, Forms, Modes, Screen
     }
 };
 
@@ -12,21 +24,40 @@ use ratatui::{
     Terminal,
 };
 use crossbeam_channel::{
+    select,
+    tick,
     unbounded,
     Receiver,
     Sender,
 };
-use crossterm::event::{self, Event};
+use crossterm::event::{Event, KeyEventKind};
+use zeroize::Zeroizing;
+use tokio::{
+    runtime::Handle,
+    sync::{
+        mpsc::{unbounded_channel, UnboundedSender},
+        oneshot,
+    },
+};
 use std::{
     vec,
     char,
-    thread,
-    cell::RefCell,
-    io::Result,
+    cell::{Cell, RefCell},
+    future::Future,
+    io::{self, Result, Write},
     rc::Rc,
+    time::Instant,
 };
 
 
+// About three seconds at TICK_RATE
+const REVEAL_TICKS: usize = 12;
+// Typing pause before a sign up username is checked with the server
+const NAME_CHECK_TICKS: usize = 2;
+
+// Entries of Screen::Account, in order
+pub const ACCOUNT_MENU: [&str; 3] = ["Change display name", "Change password", "Log out"];
+
 pub struct App {
     pub exit:             bool,
     pub form:             Form,
@@ -34,6 +65,15 @@ pub struct App {
     pub selected_block:   Block,
     pub selected_screen:  Screen,
     pub mode:             Modes,
+    pub profiles:         Profiles,
+    pub profile_hover:    usize,          // Highlighted entry on the profile switcher
+    pub account_hover:    usize,          // ... and on the account menu
+    pub base_config:      Config,         // config.toml as loaded
+    pub config:           Config,         // ... with the active profile's preferences
+    pub pending_keys:     Vec<Key>,       // Start of a key sequence still being typed
+    pub theme:            Theme,          // Already fitted to the terminal's colors
+    pub show_help:        bool,
+    pub confirm:          Option<Confirm>, // Waiting for a yes or no
 
     // Coordination of showing line
     pub all_input:        Rc<RefCell<Vec<String>>>,
@@ -41,76 +81,132 @@ pub struct App {
     pub line_index:       usize,
     pub room_index:       usize,
 
-    pub messages:         Vec<String>,
-    pub to_ws:            Sender<String>,
-    pub from_ws:          Receiver<String>,
-    pub stop_to_ws:       Sender<bool>,
+    pub messages:         Vec<ChatMessage>,
+    pub to_ws:            Option<UnboundedSender<String>>, // Outgoing queue of the room we're in
+    pub from_ws:          Receiver<WsEvent>,
+    pub conn_state:       ConnState,
+    pub chat_stop:        Option<oneshot::Sender<()>>, // Ends the running chat task, sent or dropped
     pub room_names:       Vec<String>,
     pub room_hashes:      Vec<String>,
-    pub is_user_msg:      bool,
+    pub room_visibility:  Vec<Visibility>,
+    pub current_room:     Option<String>, // Hash of the room we're chatting in
+    pub room_in_edit:     Option<String>, // Hash of the room on the Room Edit form
+    pub recovery:         Option<(String, String)>, // Username and the security question asked for it
+    pub name_edited_at:   Option<usize>,  // Tick the sign up username last changed, until it's checked
     pub chat_scroll_state:ScrollbarState,
-    pub chat_scroll_index:usize,
+    pub chat_scroll_index:usize,          // Lines scrolled up from the bottom
+    pub chat_width:       Cell<u16>,      // Set by draw_ui, used to wrap
+    pub chat_height:      Cell<u16>,      // Set by draw_ui, used to page
+    pub chat_rows:        RefCell<ChatRows>, // Wrapped messages, so frames don't wrap them again
+    pub unread:           usize,          // Arrived while scrolled up
+    pub notices:          Notices,        // Session outcomes, shown on the status bar and as toasts
+
+    // Session calls run on this runtime and report back through replies
+    pub runtime:          Handle,
+    pub replies:          Sender<SessionReply>,
+    pub from_session:     Receiver<SessionReply>,
+    pub pending:          usize,
+    pub loading:          &'static str,
+    pub ticks:            usize,
 }
 
 impl App {
-    pub fn new() -> Self {
-        let (tx_to_ws, rx_from_cli)         = unbounded::<String>(); // CLI -> WebSocket
-        let (tx_to_cli, rx_from_ws)         = unbounded::<String>(); // WebSocket -> CLI
-        let (stop_sender, stop_from_cli)    = unbounded::<bool>();   // Breaking channels
+    pub fn new(runtime: Handle, profiles: Profiles, base_config: Config) -> Self {
+        let (tx_to_cli, rx_from_ws) = unbounded::<WsEvent>(); // WebSocket -> CLI
 
+        let (sess, load_err) = Session::new(profiles.current(), tx_to_cli);
+        let mut app = Self::from_session(sess, profiles, base_config, runtime, rx_from_ws);
+        if let Some(e) = load_err {
+            app.notify(Severity::Error, format!("Not logged in: {e}"));
+        }
+        app
+    }
+
+    // Builds the app around an already made Session and the CLI side of its chat events
+    pub fn from_session(
+        sess: Session,
+        profiles: Profiles,
+        base_config: Config,
+        runtime: Handle,
+        rx_from_ws: Receiver<WsEvent>,
+    ) -> Self {
         let screen:      Screen; // This kind of approach is needed for future token conditions
         let formm:       Form;   // If you know, you know
-        let mut rnames:  Vec<String> = vec!["".to_string()];
-        let mut rhashes: Vec<String> = vec!["".to_string()];
+        let (replies, from_session) = unbounded::<SessionReply>();
+        // Preferences were checked on startup
+        let current = profiles.current();
+        let config  = base_config
+            .for_profile(&current.name, &current.preferences)
+            .unwrap_or_else(|_| base_config.clone());
+        let theme = Theme::from_config(&config.theme).unwrap_or_default().for_depth(ColorDepth::detect());
 
-        let sess = Session::new(rx_from_cli, tx_to_cli, stop_from_cli);
         match sess.token.clone() {
+            // Token is checked in background, a failing ping sends us to Sign In
             Some(_value) => {
-                let pong = sess.ping();
-                if pong.contains_key("ok") {
-                    screen = Screen::Main;
-                    formm  = Form::new(None, None, None);
-                    let rooms = sess.room_publist();
-                    if rooms.contains_key("ok") {
-                        rnames  = rooms.get("names").unwrap().to_vec();
-                        rhashes = rooms.get("hashes").unwrap().to_vec();
-                    }
-                } else {
-                    screen = Screen::Form;
-                    formm  = Form::new(Some(Forms::SignIn), Some(2), None);
-                }
+                screen = Screen::Main;
+                formm  = Form::new(None, None);
             },
 
             None         => {
                 screen = Screen::FormChoose;
-                let opts = vec!["SignUp".to_string(), "LogIn".to_string()];
-                formm  = Form::new(None, None, Some(opts));
+                formm  = choose_form();
             },
         }
 
         let inp = Rc::clone(&formm.inputs[0]);
 
-        Self {
+        let mut app = Self {
             exit:             false,
             form:             formm,
             session:          sess,
             selected_block:   Block::Rooms,
             selected_screen:  screen,
             mode:             Modes::Normal,
+            profile_hover:    profiles.active,
+            account_hover:    0,
+            profiles,
+            base_config,
+            config,
+            pending_keys:     Vec::new(),
+            theme,
+            show_help:        false,
+            confirm:          None,
             all_input:        inp,
             char_index:       0,
             line_index:       0,
             room_index:       0,
             messages:         Vec::new(),
-            to_ws:            tx_to_ws,
+            to_ws:            None,
             from_ws:          rx_from_ws,
-            stop_to_ws:       stop_sender,
-            room_names:       rnames,
-            room_hashes:      rhashes,
-            is_user_msg:      true,
+            conn_state:       ConnState::Offline,
+            chat_stop:        None,
+            room_names:       vec!["".to_string()],
+            room_hashes:      vec!["".to_string()],
+            room_visibility:  vec![Visibility::Public],
+            current_room:     None,
+            room_in_edit:     None,
+            recovery:         None,
+            name_edited_at:   None,
             chat_scroll_state:ScrollbarState::new(0),
             chat_scroll_index:0,
+            chat_width:       Cell::new(u16::MAX),
+            chat_height:      Cell::new(0),
+            chat_rows:        RefCell::default(),
+            unread:           0,
+            notices:          Notices::default(),
+            runtime,
+            replies,
+            from_session,
+            pending:          0,
+            loading:          "",
+            ticks:            0,
+        };
+
+        if app.session.token.is_some() {
+            let ses = app.session.clone();
+            app.spawn_call("Checking session", async move { ses.ping().await }, SessionReply::Ping);
         }
+        app
     }
 
     pub fn update_input(&mut self) {
@@ -119,17 +215,113 @@ impl App {
 
     pub fn jump2form(&mut self) {
         let selected_form = match self.form.options[self.form.selected_input].as_str() {
-            "SignUp" => Forms::SignUp,
-            _        => Forms::SignIn,
+            "SignUp"  => Forms::SignUp,
+            "Recover" => Forms::Recovery,
+            _         => Forms::SignIn,
         };
 
-        let n_inputs = match selected_form {
-            Forms::SignUp => 4,
-            _             => 2,
+        self.selected_screen = Screen::Form;
+        self.form = match selected_form {
+            Forms::SignUp   => Form::new(Some(selected_form), None),
+            Forms::Recovery => {
+                self.recovery = None;
+                let mut form = Form::new(Some(selected_form), None);
+                form.active = 1;
+                form.note   = Some("Submit your username to get its security question".to_string());
+                form
+            },
+            _               => self.sign_in_form(),
         };
+    }
 
-        self.selected_screen = Screen::Form;
-        self.form = Form::new(Some(selected_form), Some(n_inputs), None);
+    // Username comes pre-filled from the profile when it has one
+    fn sign_in_form(&self) -> Form {
+        let form = Form::new(Some(Forms::SignIn), None);
+        if let Some(name) = &self.profiles.current().username {
+            form.set_value(0, name.clone());
+        }
+        form
+    }
+
+    pub fn open_profiles(&mut self) {
+        self.profile_hover   = self.profiles.active;
+        self.selected_screen = Screen::Profiles;
+    }
+
+    pub fn open_account(&mut self) {
+        self.selected_screen = Screen::Account;
+    }
+
+    // Runs the hovered entry of ACCOUNT_MENU
+    pub fn account_action(&mut self) {
+        match self.account_hover {
+            0 => {
+                self.form = Form::new(Some(Forms::Rename), None);
+                self.form.set_value(0, self.session.show_name.clone().unwrap_or_default());
+                self.selected_screen = Screen::Form;
+            },
+            1 => {
+                self.form = Form::new(Some(Forms::Repass), None);
+                self.selected_screen = Screen::Form;
+            },
+            _ => self.confirm = Some(Confirm::Logout),
+        }
+    }
+
+    // Forgets the account, the stored token is already gone by now
+    fn signed_out(&mut self) {
+        self.leave_room();
+        self.session.token     = None;
+        self.session.show_name = None;
+        self.room_names.clear();
+        self.room_hashes.clear();
+        self.room_visibility.clear();
+        self.room_index        = 0;
+        self.account_hover     = 0;
+        self.go_home();
+    }
+
+    // Back to wherever the current profile's login state puts us, a draft message is kept
+    pub fn go_home(&mut self) {
+        match self.session.token {
+            Some(_) => {
+                self.selected_screen = Screen::Main;
+                if !matches!(self.form.kind, Forms::Typing) {
+                    self.form = Form::new(None, None);
+                    self.update_input();
+                }
+            },
+            None    => {
+                self.selected_screen = Screen::FormChoose;
+                self.form = choose_form();
+            },
+        }
+    }
+
+    // Cycles through built-in and user themes, until the next start
+    pub fn next_theme(&mut self) {
+        let names = Theme::available();
+        let next  = names.iter()
+            .position(|n| *n == self.theme.name)
+            .map_or(0, |i| (i + 1) % names.len());
+
+        // The user's own [theme] colors stay on top of whichever theme is picked
+        let config = ThemeConfig { name: Some(names[next].clone()), colors: self.config.theme.colors.clone() };
+        match Theme::from_config(&config) {
+            Ok(theme) => {
+                self.theme = theme.for_depth(ColorDepth::detect());
+                self.notify(Severity::Info, format!("Theme: {}", self.theme.name));
+            },
+            Err(e)    => self.notify(Severity::Error, e),
+        }
+    }
+
+    // Drops the Session, its chat task and everything shown for it, then starts over on another profile
+    pub fn switch_profile(&mut self, index: usize) {
+        self.leave_room();
+        let mut profiles = self.profiles.clone();
+        profiles.active = index;
+        *self = App::new(self.runtime.clone(), profiles, self.base_config.clone());
     }
 
     pub fn move_cursor_left(&mut self) {
@@ -142,6 +334,12 @@ impl App {
         self.char_index = self.clamp_cursor(cursor_moved_right);
     }
 
+    // Arrow keys follow what's on screen, which is backwards inside RTL runs
+    pub fn move_cursor_visual(&mut self, rightwards: bool) {
+        let line = self.all_input.borrow()[self.line_index].clone();
+        self.char_index = bidi::visual_step(&line, self.char_index, rightwards);
+    }
+
     // Avoids cursor from going out of bound
     fn clamp_cursor(&self, new_cursor_pos: usize) -> usize {
         new_cursor_pos.clamp(0, self.all_input.borrow_mut()[self.line_index].chars().count())
@@ -156,7 +354,7 @@ impl App {
     }
 
     pub fn set_curser(&mut self) {
-        self.char_index = self.all_input.borrow()[self.line_index].len();
+        self.char_index = self.all_input.borrow()[self.line_index].chars().count();
     }
 
     // Gets index of selected char with respect to self.char_index
@@ -202,7 +400,7 @@ impl App {
     pub fn go_top_line(&mut self) {
         if self.line_index != 0 {
             self.line_index = self.line_index.saturating_sub(1);
-            self.clamp_cursor(self.char_index);
+            self.char_index = self.clamp_cursor(self.char_index);
         }
     }
 
@@ -210,29 +408,28 @@ impl App {
         let new_line_index = self.line_index.saturating_add(1);
         if new_line_index < self.all_input.borrow().len() {
             self.line_index = new_line_index;
-            self.clamp_cursor(self.char_index);
+            self.char_index = self.clamp_cursor(self.char_index);
         }
     }
 
+    // Char index where the word behind the cursor starts
+    fn word_start(&self) -> usize {
+        let chars: Vec<char> = self.all_input.borrow()[self.line_index].chars().take(self.char_index).collect();
+        let mut i = chars.len();
+        while i > 0 && chars[i - 1].is_whitespace()  { i -= 1 }  // Skip trailing spaces
+        while i > 0 && !chars[i - 1].is_whitespace() { i -= 1 }  // Then the word itself
+        i
+    }
+
     // Removes one word behind
     pub fn delete_word(&mut self) {
         let is_not_cursor_leftmost = self.char_index != 0;
         if is_not_cursor_leftmost {
+            let new_cursor_pos = self.word_start();
             let mut borrowed = self.all_input.borrow_mut();
-            let before_cursor: String = borrowed[self.line_index].chars().take(self.char_index).collect();
-            let new_cursor_pos = before_cursor
-                .trim_end()              // Remove trailing spaces
-                .rfind(' ')              // Find last space
-                .map(|i| i + 1)          // Position after space
-                .unwrap_or(0);           // If no space, go to start
-
-            let after_cursor: String = borrowed[self.line_index].chars().skip(self.char_index).collect();
-
-            borrowed[self.line_index] = format!(
-                "{}{}",
-                &before_cursor[..new_cursor_pos],
-                after_cursor
-            );
+            let before_cursor = borrowed[self.line_index].chars().take(new_cursor_pos);
+            let after_cursor  = borrowed[self.line_index].chars().skip(self.char_index);
+            borrowed[self.line_index] = before_cursor.chain(after_cursor).collect();
 
             self.char_index = new_cursor_pos;
         }
@@ -240,68 +437,115 @@ impl App {
 
     // Going one word foreward
     pub fn foreword(&mut self) {
-        let space_indices: Vec<usize> = self.all_input.borrow()[self.line_index][self.char_index..]
-            .trim_start()
-            .match_indices(" ")
-            .map(|(i, _)| i + self.char_index)
-            .collect();
-
-        if space_indices.len() != 0 {
-            self.char_index = space_indices[0];
-        } else {
-            self.char_index = self.all_input.borrow()[self.line_index].len();
-        }
+        let chars: Vec<char> = self.all_input.borrow()[self.line_index].chars().collect();
+        let mut i = self.char_index;
+        while i < chars.len() && chars[i].is_whitespace()  { i += 1 }
+        while i < chars.len() && !chars[i].is_whitespace() { i += 1 }
+        self.char_index = i;
     }
 
     // Going one word backward
     pub fn backword(&mut self) {
-        let before_cursor: String = self.all_input.borrow()[self.line_index].chars().take(self.char_index).collect();
-        let new_cursor_pos = before_cursor
-            .trim_end()
-            .rfind(' ')
-            .map(|i| i + 1)
-            .unwrap_or(0);
-
-        self.char_index = new_cursor_pos
+        self.char_index = self.word_start();
     }
 
-    pub fn toggle_form_bool(&mut self) {
-        let is_last = self.form.selected_input == self.form.inputs.len() - 1;
-        if is_last {
-            self.form.switch_pub();
+    // Word jumps by screen direction, in a RTL line going right means going back
+    pub fn word_visual(&mut self, rightwards: bool) {
+        let rtl = bidi::is_rtl(&self.all_input.borrow()[self.line_index]);
+        if rightwards != rtl {
+            self.foreword();
         } else {
-            self.mode = Modes::Insert;
-            self.set_curser();
+            self.backword();
         }
     }
 
-    fn vec2str(&self, vec: Vec<String>) -> String {
-        let mut res = "".to_string();
-        for s in vec.clone().iter() {
-            res += s;
+    // Toggles and choices change in place, the rest are typed into
+    pub fn edit_field(&mut self) {
+        let field = self.form.selected_field();
+        match (&field.kind, field.fixed) {
+            (_, true) => {
+                let label = field.label.trim_end_matches(':');
+                self.notify(Severity::Info, format!("{label} can't be changed here"));
+            },
+            (InputKind::Boolean | InputKind::Choice(_), _) => self.form.cycle_selected(),
+            _ => {
+                self.mode = Modes::Insert;
+                self.set_curser();
+            },
         }
-        res
     }
 
-    fn str2vec(&self, vec: String) -> Vec<String> {
-        let parts: Vec<String> = vec.split("\\")
-            .filter(|s| !s.is_empty())
-            .map(|s| s.to_string())
-            .collect();
-        parts
+    // Shows secrets in clear for a few seconds, or hides them again
+    pub fn reveal_secrets(&mut self) {
+        self.form.revealed_until = match self.form.revealed_until {
+            Some(until) if until > self.ticks => None,
+            _                                 => Some(self.ticks + REVEAL_TICKS),
+        };
+    }
+
+    // Joins typed lines into a message body, dropping the continuation backslashes
+    fn input_body(&self) -> String {
+        self.all_input.borrow()
+            .iter()
+            .map(|line| line.strip_suffix('\\').unwrap_or(line))
+            .collect::<Vec<&str>>()
+            .join("\n")
     }
 
-    fn add_msg(&mut self, msg: &mut Vec<String>) {
-        self.messages.push("User1:".to_string());
-        {
-            for (i, _) in msg.clone().iter().enumerate() {
-                msg[i] = msg[i].replace("\\", "");
-                self.messages.push(msg[i].to_string())
-            }
-            self.messages.push("".to_string());
+    fn add_msg(&mut self, msg: ChatMessage) {
+        // Server may echo our own message back
+        if self.messages.iter().any(|m| m.id == msg.id) {
+            return
         }
-        self.reset_cursor();
-        self.reset_line();
+
+        self.messages.push(msg);
+
+        // Only follow new messages when already at the bottom
+        if self.chat_scroll_index != 0 {
+            self.chat_scroll_index += last_message_lines(self, &self.theme);
+            self.unread += 1;
+        }
+        self.sync_chat_scroll();
+    }
+
+    fn max_chat_scroll(&self) -> usize {
+        chat_lines(self, &self.theme).len().saturating_sub(self.chat_height.get() as usize)
+    }
+
+    fn sync_chat_scroll(&mut self) {
+        let max_top = self.max_chat_scroll();
+        self.chat_scroll_index = self.chat_scroll_index.min(max_top);
+        if self.chat_scroll_index == 0 {
+            self.unread = 0;
+        }
+        self.chat_scroll_state = self.chat_scroll_state
+            .content_length(max_top)
+            .position(max_top - self.chat_scroll_index);
+    }
+
+    pub fn scroll_chat_up(&mut self, lines: usize) {
+        self.chat_scroll_index = self.chat_scroll_index.saturating_add(lines);
+        self.sync_chat_scroll();
+    }
+
+    pub fn scroll_chat_down(&mut self, lines: usize) {
+        self.chat_scroll_index = self.chat_scroll_index.saturating_sub(lines);
+        self.sync_chat_scroll();
+    }
+
+    pub fn scroll_chat_top(&mut self) {
+        self.chat_scroll_index = usize::MAX;
+        self.sync_chat_scroll();
+    }
+
+    pub fn scroll_chat_bottom(&mut self) {
+        self.chat_scroll_index = 0;
+        self.sync_chat_scroll();
+    }
+
+    // One page keeps a line of the previous one for context
+    pub fn chat_page(&self) -> usize {
+        (self.chat_height.get() as usize).saturating_sub(1).max(1)
     }
 
     pub fn submit_message(&mut self) {
@@ -316,19 +560,34 @@ impl App {
 
             match self.selected_screen {
                 Screen::Main => {
-                    let mut borrowed = self.all_input.borrow_mut().to_vec();
-                    self.add_msg(&mut borrowed);
+                    let Some(room) = self.current_room.clone() else {
+                        self.notify(Severity::Warning, "Enter a room first");
+                        return
+                    };
+
+                    let sender = self.session.show_name.clone().unwrap_or_default();
+                    let msg = ChatMessage::new(&room, &sender, self.input_body());
+                    self.send_message(&msg);
+                    self.scroll_chat_bottom();
+                    self.add_msg(msg);
                     *self.all_input.borrow_mut() = vec!["".to_string()];
+                    self.reset_cursor();
+                    self.reset_line();
                 },
 
+                // Enter moves down the form, on the last field it submits
                 Screen::Form => {
-                    match self.form.kind {
-                        Forms::SignIn => {
-                            let show_name = &self.form.inputs[0].borrow().clone()[self.line_index];
-                            let password  = &self.form.inputs[1].borrow().clone()[self.line_index];
-                            self.session.login(show_name, password);
-                        },
-                        _ => {}
+                    if self.form.is_last_field() {
+                        self.mode = Modes::Normal;
+                        self.submit_form();
+                    } else {
+                        self.form.selected_input += 1;
+                        self.update_input();
+                        self.set_curser();
+                        // Toggles and choices aren't typed into
+                        if !matches!(self.form.selected_field().kind, InputKind::Text | InputKind::Secret) {
+                            self.mode = Modes::Normal;
+                        }
                     }
                 },
 
@@ -337,68 +596,405 @@ impl App {
         }
     }
 
+    // Called after each key typed into a form
+    pub fn form_edited(&mut self) {
+        if matches!(self.form.kind, Forms::SignUp) && self.form.selected_input == 0 {
+            self.name_edited_at = Some(self.ticks);
+        }
+    }
+
+    // Asks the server about the sign up username once typing has paused
+    fn check_name(&mut self) {
+        let Some(edited) = self.name_edited_at else { return };
+        if self.ticks < edited + NAME_CHECK_TICKS {
+            return
+        }
+        self.name_edited_at = None;
+        // Names failing the local rules aren't worth a request
+        if !matches!(self.form.kind, Forms::SignUp) || self.form.error(0).is_some() {
+            return
+        }
+
+        let ses  = self.session.clone();
+        let name = self.form.value(0);
+        self.spawn_call("Checking username", async move {
+            // Server answers "error" for names it doesn't know
+            match ses.user_exists(&name).await {
+                Ok(_)                        => Ok((name, true)),
+                Err(SessionError::Server(_)) => Ok((name, false)),
+                Err(e)                       => Err(e),
+            }
+        }, SessionReply::NameTaken);
+    }
+
+    // Nothing leaves until every field in play passes its rules
     pub fn submit_form(&mut self) {
+        if !self.form.is_valid() {
+            self.form.attempted = true;
+            self.notify(Severity::Warning, "Fix the marked fields first");
+            return
+        }
+        self.send_form();
+        // Whatever was needed is on its way, typed secrets aren't kept around
+        self.form.clear_secrets();
+    }
+
+    fn send_form(&mut self) {
+        let ses = self.session.clone();
         match self.form.kind {
             Forms::SignUp => {
-                let show_name        = &self.form.inputs[0].borrow_mut()[0].clone();
-                let password         = &self.form.inputs[1].borrow_mut()[0].clone();
-                let related_question = &self.form.inputs[2].borrow_mut()[0].clone();
-                let related_answer   = &self.form.inputs[3].borrow_mut()[0].clone();
+                let show_name        = self.form.value(0);
+                let password         = Zeroizing::new(self.form.value(1));
+                let related_question = self.form.value(3);
+                let related_answer   = self.form.value(4);
+
+                self.spawn_call("Signing up", async move {
+                    ses.signup(&show_name, &password, &related_question, &related_answer).await
+                }, SessionReply::SignUp);
+            },
+
+            Forms::SignIn => {
+                let show_name        = self.form.value(0);
+                let password         = Zeroizing::new(self.form.value(1));
+                let remember         = self.form.value(2) == "true";
 
-                let response = self.session.signup(show_name, password,
-                    related_question, related_answer);
+                self.spawn_call("Signing in", async move {
+                    ses.login(&show_name, &password, remember).await
+                }, SessionReply::LogIn);
+            },
 
-                if response.contains_key("ok") {
-                    self.selected_screen = Screen::FormChoose;
+            Forms::RoomCreator => {
+                let name       = self.form.value(0);
+                let visibility = Visibility::from_label(&self.form.value(1)).unwrap_or(Visibility::Public);
+
+                self.spawn_call("Creating room", async move {
+                    ses.room_build(&name, visibility).await
+                }, SessionReply::RoomBuilt);
+            },
+
+            Forms::Rename => {
+                let show_name = self.form.value(0);
+                self.spawn_call("Renaming", async move {
+                    ses.user_rename(&show_name).await
+                }, SessionReply::Renamed);
+            },
+
+            Forms::Repass => {
+                let current  = Zeroizing::new(self.form.value(0));
+                let new_pass = Zeroizing::new(self.form.value(1));
+                self.spawn_call("Changing password", async move {
+                    ses.user_repass(&current, &new_pass).await
+                }, SessionReply::Repassed);
+            },
+
+            // Asks for the question first, then sends the answer along with the new password
+            Forms::Recovery => {
+                let show_name = self.form.value(0).trim().to_string();
+                let answer    = self.form.value(1);
+                let new_pass  = Zeroizing::new(self.form.value(2));
+
+                // A changed username needs its own question, the rest waits for it
+                if self.recovery.as_ref().map(|(name, _)| name) != Some(&show_name) {
+                    self.recovery            = None;
+                    self.form.active         = 1;
+                    self.form.note           = None;
+                    self.form.selected_input = 0;
+                    self.update_input();
+                    self.spawn_call("Fetching question", async move {
+                        ses.recovery_question(&show_name).await.map(|q| (show_name, q))
+                    }, SessionReply::Question);
+                } else {
+                    self.spawn_call("Resetting password", async move {
+                        ses.recover(&show_name, &answer, &new_pass).await.map(|_| show_name)
+                    }, SessionReply::Recovered);
                 }
+            },
+
+            Forms::RoomEdit => {
+                let Some(hash) = self.room_in_edit.clone() else { return };
+                let new_name = self.form.value(0).trim().to_string();
+                let old_name = self.room_hashes.iter()
+                    .position(|h| *h == hash)
+                    .map(|i| self.room_names[i].clone());
 
-                let response = self.session.room_publist();
-                if response.contains_key("ok") {
-                    self.room_names  = response.get("names").unwrap().to_vec();
-                    self.room_hashes = response.get("hashes").unwrap().to_vec();
+                if old_name.as_deref() == Some(new_name.as_str()) {
+                    self.go_home();
+                } else {
+                    self.spawn_call("Renaming room", async move {
+                        ses.room_rename(&hash, &new_name).await
+                    }, SessionReply::RoomRenamed);
                 }
             },
 
-            Forms::SignIn => {
-                let show_name        = &self.form.inputs[0].borrow_mut()[0].clone();
-                let password         = &self.form.inputs[1].borrow_mut()[0].clone();
+            _ => {},
+        }
+    }
+
+    // Room Edit form filled in with the hovered room
+    pub fn edit_room(&mut self) {
+        let Some(hash) = self.room_hashes.get(self.room_index).cloned() else {
+            self.notify(Severity::Warning, "No room to edit");
+            return
+        };
+        self.form = Form::new(Some(Forms::RoomEdit), None);
+        self.form.set_value(0, self.room_names[self.room_index].clone());
+        if let Some(visibility) = self.room_visibility.get(self.room_index) {
+            self.form.set_value(1, visibility.label());
+        }
+        self.room_in_edit    = Some(hash);
+        self.selected_screen = Screen::Form;
+    }
 
-                let response = self.session.login(show_name, password);
+    pub fn ask_close_room(&mut self) {
+        match self.room_hashes.get(self.room_index) {
+            Some(hash) => self.confirm = Some(Confirm::CloseRoom {
+                hash: hash.clone(),
+                name: self.room_names[self.room_index].clone(),
+            }),
+            None       => self.notify(Severity::Warning, "No room to close"),
+        }
+    }
+
+    pub fn answer_confirm(&mut self, yes: bool) {
+        let Some(confirm) = self.confirm.take() else { return };
+        if !yes {
+            return
+        }
+        match confirm {
+            Confirm::CloseRoom { hash, .. } => {
+                let ses = self.session.clone();
+                self.spawn_call("Closing room", async move {
+                    ses.room_close(&hash).await.map(|_| hash)
+                }, SessionReply::RoomClosed);
+            },
+            Confirm::Logout => {
+                let ses = self.session.clone();
+                self.spawn_call("Logging out", async move { ses.logout().await }, SessionReply::LoggedOut);
+            },
+        }
+    }
+
+    fn refresh_rooms(&mut self) {
+        let ses = self.session.clone();
+        self.spawn_call("Loading rooms", async move { ses.room_list().await }, SessionReply::Rooms);
+    }
+
+    // Runs a Session call on the runtime, its outcome comes back as AppEvent::Session
+    fn spawn_call<T, F>(
+        &mut self,
+        label: &'static str,
+        call: F,
+        reply: fn(std::result::Result<T, SessionError>) -> SessionReply,
+    ) where
+        F: Future<Output = std::result::Result<T, SessionError>> + Send + 'static,
+        T: Send + 'static,
+    {
+        self.pending += 1;
+        self.loading = label;
+        let replies = self.replies.clone();
+        self.runtime.spawn(async move {
+            let _ = replies.send(reply(call.await));
+        });
+    }
+
+    fn on_reply(&mut self, reply: SessionReply) {
+        // Chat task isn't counted as pending, it lives as long as the room
+        if !matches!(reply, SessionReply::ChatClosed(_)) {
+            self.pending = self.pending.saturating_sub(1);
+        }
 
-                if response.contains_key("ok") {
+        match reply {
+            SessionReply::Ping(response) => {
+                if self.report(response).is_some() {
+                    self.refresh_rooms();
+                } else {
+                    self.selected_screen = Screen::Form;
+                    self.form = self.sign_in_form();
+                }
+            },
+
+            SessionReply::SignUp(response) => {
+                if let Some(message) = self.report(response) {
+                    self.notify(Severity::Success, message);
+                    self.selected_screen = Screen::FormChoose;
+                    self.form = choose_form();
+                }
+            },
+
+            SessionReply::LogIn(response) => {
+                if let Some(login) = self.report(response) {
+                    self.session.token     = Some(login.token);
+                    self.notify(Severity::Success, format!("Logged in as {}", login.show_name));
+                    self.session.show_name = Some(login.show_name);
                     self.selected_screen = Screen::Main;
-                    *self.all_input.borrow_mut() = vec![String::new()];
-                    let response = self.session.room_publist();
-                    if response.contains_key("ok") {
-                        self.room_names  = response.get("names").unwrap().to_vec();
-                        self.room_hashes = response.get("hashes").unwrap().to_vec();
+                    self.form = Form::new(None, None);
+                    self.update_input();
+                    self.refresh_rooms();
+                }
+            },
+
+            SessionReply::Rooms(response) => {
+                if let Some(rooms) = self.report(response) {
+                    if let Some(problem) = rooms.problem {
+                        self.notify(Severity::Warning, problem);
                     }
+                    self.room_names      = rooms.names;
+                    self.room_hashes     = rooms.hashes;
+                    self.room_visibility = rooms.visibility;
+                    // Same spot in the list when it's still there, the last room when the list shrank
+                    self.room_index      = self.room_index.min(self.room_names.len().saturating_sub(1));
                 }
             },
 
-            Forms::RoomCreator => {
-                let name      = &self.form.inputs[0].borrow_mut()[0].clone();
-                let is_public = &self.form.inputs[1].borrow_mut()[0].clone();
+            SessionReply::RoomBuilt(response) => {
+                if self.report(response).is_some() {
+                    self.notify(Severity::Success, "Room created");
+                    self.selected_screen = Screen::Main;
+                    self.form = Form::new(None, None);
+                    self.update_input();
+                    self.refresh_rooms();
+                }
+            },
 
-                let response  = self.session.room_build(name, is_public);
+            SessionReply::RoomRenamed(response) => {
+                if self.report(response).is_some() {
+                    self.notify(Severity::Success, "Room renamed");
+                    self.room_in_edit = None;
+                    self.go_home();
+                    self.refresh_rooms();
+                }
+            },
 
-                if response.contains_key("ok") {
-                    self.selected_screen = Screen::Main;
-                    *self.all_input.borrow_mut() = vec![String::new()];
+            SessionReply::RoomClosed(response) => {
+                if let Some(hash) = self.report(response) {
+                    self.notify(Severity::Success, "Room closed");
+                    // Nothing left to chat in
+                    if self.current_room.as_ref() == Some(&hash) {
+                        self.leave_room();
+                    }
+                    self.refresh_rooms();
                 }
             },
 
-            _ => {},
+            SessionReply::Renamed(response) => {
+                if let Some(show_name) = self.report(response) {
+                    self.notify(Severity::Success, format!("Display name is now {show_name}"));
+                    self.session.show_name = Some(show_name);
+                    self.open_account();
+                }
+            },
+
+            SessionReply::Repassed(response) => {
+                if self.report(response).is_some() {
+                    self.notify(Severity::Success, "Password changed");
+                    self.open_account();
+                }
+            },
+
+            // Signed out here whatever the server says, as long as the token left the disk
+            SessionReply::LoggedOut(response) => {
+                match response {
+                    Ok(_)                           => self.notify(Severity::Success, "Logged out"),
+                    Err(e @ SessionError::Store(_)) => {
+                        self.notify(Severity::Error, format!("Still logged in, {e}"));
+                        return
+                    },
+                    Err(e)                          => self.notify(Severity::Warning, format!("Logged out here, but the server said: {e}")),
+                }
+                self.signed_out();
+            },
+
+            // Dropped once the form moved on or the username changed meanwhile
+            SessionReply::Question(response) => {
+                if let Some(found) = self.report(response) {
+                    if self.is_recovering(&found.0) {
+                        self.form.note = Some(format!("Q: {}", found.1));
+                        self.recovery  = Some(found);
+                        self.form.active         = self.form.inputs.len();
+                        self.form.attempted      = false;
+                        self.form.selected_input = 1;
+                        self.update_input();
+                    }
+                }
+            },
+
+            SessionReply::Recovered(response) => {
+                if let Some(show_name) = self.report(response) {
+                    self.notify(Severity::Success, "Password reset, log in with the new one");
+                    if self.is_recovering(&show_name) {
+                        self.recovery = None;
+                        self.form = Form::new(Some(Forms::SignIn), None);
+                        self.form.set_value(0, show_name);
+                        self.update_input();
+                    }
+                }
+            },
+
+            SessionReply::NameTaken(response) => {
+                if let Some((name, true)) = self.report(response) {
+                    if matches!(self.form.kind, Forms::SignUp) {
+                        self.form.taken.push(name);
+                    }
+                }
+            },
+
+            SessionReply::ChatClosed(response) => {
+                // A stopped task was already cleaned up, only the current room's giving up matters
+                let gave_up = self.chat_stop.as_ref().is_some_and(|stop| stop.is_closed());
+                self.report(response);
+                if gave_up {
+                    self.leave_room();
+                    self.notify(Severity::Warning, "Chat closed, enter the room again to reconnect");
+                }
+            },
+        }
+    }
+
+    // Recovery form is still up and still about this username
+    fn is_recovering(&self, show_name: &str) -> bool {
+        matches!(self.form.kind, Forms::Recovery) && self.form.value(0).trim() == show_name
+    }
+
+    pub fn notify(&mut self, severity: Severity, text: impl Into<String>) {
+        self.notices.push(severity, text, self.ticks);
+    }
+
+    // Name of the room we're chatting in, as listed
+    pub fn current_room_name(&self) -> Option<&str> {
+        let hash = self.current_room.as_ref()?;
+        let index = self.room_hashes.iter().position(|h| h == hash)?;
+        self.room_names.get(index).map(String::as_str)
+    }
+
+    // Keeps the failure around for draw_ui and hands back the value otherwise
+    fn report<T>(&mut self, response: std::result::Result<T, SessionError>) -> Option<T> {
+        match response {
+            Ok(value) => Some(value),
+            Err(e) => {
+                self.notify(Severity::Error, e.to_string());
+                None
+            },
         }
     }
 
+    // Previous room's task is stopped first, each room gets its own outgoing queue
     pub fn enter_room(&mut self) {
-        let ses = self.session.clone();
-        let room_hash = self.room_hashes[self.room_index].to_owned();
-        thread::spawn(move || {
-            if let Err(e) = ses.chat_connect(&room_hash) {
-                eprintln!("WebSocket thread error: {}", e);
-            }
+        let Some(room_hash) = self.room_hashes.get(self.room_index).cloned() else {
+            self.notify(Severity::Warning, "No room to enter");
+            return
+        };
+        self.leave_room();
+
+        let (stop, stopped)   = oneshot::channel();
+        let (to_ws, from_cli) = unbounded_channel::<String>(); // CLI -> WebSocket
+        self.chat_stop        = Some(stop);
+        self.to_ws            = Some(to_ws);
+        self.current_room     = Some(room_hash.clone());
+        let ses     = self.session.clone();
+        let replies = self.replies.clone();
+        self.runtime.spawn(async move {
+            let closed = ses.chat_connect(&room_hash, from_cli, stopped).await;
+            let _ = replies.send(SessionReply::ChatClosed(closed));
         });
 
         // Shift cursor to typing box
@@ -407,31 +1003,99 @@ impl App {
         self.set_curser();
     }
 
+    // Stops the chat task and forgets what it showed
     pub fn leave_room(&mut self) {
-        let _ = self.stop_to_ws.send(true);
+        if let Some(stop) = self.chat_stop.take() {
+            let _ = stop.send(());
+        }
+        self.to_ws             = None;
+        self.current_room      = None;
+        self.messages.clear();
+        self.unread            = 0;
+        self.chat_scroll_index = 0;
     }
 
-    pub fn send_message(&self) {
-        let msg = self.all_input.borrow();
-        let msg = self.vec2str(msg.to_vec());
-        let err = self.to_ws.send(msg); // Send to WebSocket thread
-        match err {
-            Ok(_val) => {}
-            Err(crossbeam_channel::SendError(er)) => {println!("ERROR: {}", er)}
+    pub fn send_message(&self, msg: &ChatMessage) {
+        // Chat task buffers it if the socket is down
+        if let Some(to_ws) = &self.to_ws {
+            let _ = to_ws.send(msg.to_frame()); // Send to WebSocket task
         }
     }
 
-    pub fn receive_message(&mut self) {
-        let msg = self.from_ws.try_recv().ok(); // Non-blocking receive
-        match msg {
-            Some(text) => {
-                println!("{text}");
-                let mut msg = self.str2vec(text);
-                self.add_msg(&mut msg);
-            }
-            _ => {}
+    pub fn is_own(&self, msg: &ChatMessage) -> bool {
+        self.session.show_name.as_deref() == Some(msg.sender.as_str())
+    }
+
+    pub fn receive_message(&mut self, msg: ChatMessage) {
+        // Frames a stopped task delivered before it noticed
+        if self.current_room.as_deref() != Some(msg.room.as_str()) {
+            return
+        }
+        if self.should_ring(&msg) {
+            // BEL doesn't move the cursor, safe to slip in between frames
+            let _ = io::stderr().write_all(b"\x07");
+        }
+        self.add_msg(msg);
+    }
+
+    fn should_ring(&self, msg: &ChatMessage) -> bool {
+        let notify = &self.config.notifications;
+        if !notify.bell || self.is_own(msg) {
+            return false
+        }
+        match (&self.session.show_name, notify.mentions_only) {
+            (Some(name), true) => msg.body.contains(name.as_str()),
+            (None, true)       => false,
+            (_, false)         => true,
         }
     }
+
+    // Blocks until terminal input, a WebSocket frame, a Session reply or a tick shows up
+    pub fn next_event(&self, input: &Receiver<Event>, ticker: &Receiver<Instant>) -> AppEvent {
+        select! {
+            recv(input) -> ev => match ev {
+                Ok(ev) => AppEvent::Input(ev),
+                Err(_) => AppEvent::Tick,
+            },
+            recv(self.from_ws) -> msg => match msg {
+                Ok(WsEvent::Message(msg)) => AppEvent::Ws(msg),
+                Ok(WsEvent::State(conn))  => AppEvent::Conn(conn),
+                Ok(WsEvent::Problem(e))   => AppEvent::Notice(Severity::Warning, e),
+                Err(_)   => AppEvent::Tick,
+            },
+            recv(self.from_session) -> reply => match reply {
+                Ok(reply) => AppEvent::Session(reply),
+                Err(_)    => AppEvent::Tick,
+            },
+            recv(ticker) -> _ => AppEvent::Tick,
+        }
+    }
+
+    pub fn handle_event(&mut self, ev: AppEvent) -> Result<()> {
+        match ev {
+            AppEvent::Input(Event::Key(key)) if key.kind != KeyEventKind::Release => {
+                logics::key_bindings(self, key)?;
+            },
+            AppEvent::Ws(msg) => self.receive_message(msg),
+            AppEvent::Conn(conn) => self.conn_state = conn,
+            AppEvent::Notice(severity, text) => self.notify(severity, text),
+            AppEvent::Session(reply) => self.on_reply(reply),
+            AppEvent::Tick => {
+                self.ticks = self.ticks.wrapping_add(1);
+                self.notices.expire(self.ticks);
+                self.check_name();
+            },
+            // Resizes only need a redraw
+            _ => {},
+        }
+        Ok(())
+    }
+}
+
+// Sign up / log in / recover picker
+fn choose_form() -> Form {
+    let opts = vec!["SignUp".to_string(), "LogIn".to_string(), "Recover".to_string()];
+    Form::new(None, Some(opts))
 }
 
 pub fn hover_over(last: usize, selected_index: &mut usize, go_next: bool) {
@@ -461,22 +1125,444 @@ pub fn run_app<B: Backend>(
     terminal: &mut Terminal<B>,
     app: &mut App,
 ) -> Result<()> {
+    let input  = events::spawn_input_reader();
+    let ticker = tick(TICK_RATE);
+
     while !app.exit {
         // Link form and input field
         match app.selected_screen {
-            Screen::FormChoose => {},
-            Screen::Main       => {
+            Screen::FormChoose | Screen::Profiles | Screen::Account => {},
+            _                                                       => {app.update_input();},
+        }
+
+        terminal.draw(|frame| draw_ui(frame, app, &app.theme))?;
+        let ev = app.next_event(&input, &ticker);
+        app.handle_event(ev)?;
+    }
+    // Chat thread shouldn't outlive the UI
+    app.leave_room();
+    Ok(())
+}
+
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::server_talk::{
+        session::{tests::canned_server, RoomList},
+        store::tests::scratch_store,
+    };
+    use crate::settings::profiles::Profile;
+    use crossbeam_channel::never;
+    use crossterm::event::{KeyCode, KeyEvent};
+    use ratatui::backend::TestBackend;
+    use reqwest::Client;
+    use std::{sync::Arc, time::Duration};
+    use tokio::runtime::Runtime;
+
+    // App on Main screen with no server behind it, WebSocket side is handed back
+    pub(crate) fn offline_app(runtime: &Runtime) -> (App, Sender<WsEvent>) {
+        let (tx_to_cli, rx_from_ws) = unbounded::<WsEvent>();
+
+        let sess = Session {
+            host:          "http://127.0.0.1:0".to_string(),
+            client:        Client::new(),
+            token:         None,
+            show_name:     None,
+            content_type:  "application/json".to_string(),
+            tls:           None,
+            store:         Arc::new(scratch_store(None)),
+            to_cli:        tx_to_cli.clone(),
+        };
+
+        let handle  = runtime.handle().clone();
+        let profile = Profile {
+            name:        "test".to_string(),
+            host:        "http://127.0.0.1:0".to_string(),
+            username:    None,
+            preferences: toml::Table::new(),
+        };
+        let profiles = Profiles { list: vec![profile], active: 0 };
+        let mut app  = App::from_session(sess, profiles, Config::default(), handle, rx_from_ws);
+        app.selected_screen = Screen::Main;
+        app.form = Form::new(None, None);
+        app.update_input();
+        (app, tx_to_cli)
+    }
+
+    pub(crate) fn buffer_text(terminal: &Terminal<TestBackend>) -> String {
+        let buffer = terminal.backend().buffer();
+        buffer.content().iter().map(|c| c.symbol()).collect()
+    }
+
+    #[test]
+    fn ws_message_renders_without_key_event() {
+        let runtime = Runtime::new().unwrap();
+        let (mut app, fake_ws) = offline_app(&runtime);
+        let mut terminal = Terminal::new(TestBackend::new(80, 20)).unwrap();
+        let (_keep_input_open, input) = unbounded::<Event>();
+
+        terminal.draw(|frame| draw_ui(frame, &app, &app.theme)).unwrap();
+        assert!(!buffer_text(&terminal).contains("hello from ws"));
+
+        app.current_room = Some("room".to_string());
+        let msg = ChatMessage::from_frame("hello from ws", "room");
+        fake_ws.send(WsEvent::Message(msg)).unwrap();
+        let ev = app.next_event(&input, &never());
+        assert!(matches!(ev, AppEvent::Ws(_)));
+        app.handle_event(ev).unwrap();
+
+        terminal.draw(|frame| draw_ui(frame, &app, &app.theme)).unwrap();
+        assert!(buffer_text(&terminal).contains("hello from ws"));
+    }
+
+    #[test]
+    fn chat_block_keys_scroll_the_messages() {
+        let runtime = Runtime::new().unwrap();
+        let (mut app, _ws) = offline_app(&runtime);
+        app.chat_width.set(40);
+        app.chat_height.set(10);
+        app.current_room = Some("room".to_string());
+        for n in 0..30 {
+            app.receive_message(ChatMessage::new("room", "bo", format!("msg {n}")));
+        }
+        app.selected_block = Block::Chat;
+        let press = |app: &mut App, code: KeyCode| {
+            logics::key_bindings(app, KeyEvent::from(code)).unwrap();
+            app.chat_scroll_index
+        };
+
+        // 32 lines in a 10 row view, 9 to a page
+        assert_eq!(press(&mut app, KeyCode::Char('k')), 1);
+        assert_eq!(press(&mut app, KeyCode::PageUp), 10);
+        assert_eq!(press(&mut app, KeyCode::Char('j')), 9);
+        assert_eq!(press(&mut app, KeyCode::Home), 22);
+        assert_eq!(press(&mut app, KeyCode::PageUp), 22);
+        assert_eq!(press(&mut app, KeyCode::PageDown), 13);
+        assert_eq!(press(&mut app, KeyCode::End), 0);
+        assert_eq!(press(&mut app, KeyCode::Char('j')), 0);
+    }
+
+    #[test]
+    fn cycled_themes_keep_the_users_colors() {
+        let runtime = Runtime::new().unwrap();
+        let (mut app, _ws) = offline_app(&runtime);
+        app.config.theme = toml::from_str("border = \"#112233\"").unwrap();
+        let depth  = ColorDepth::detect();
+        let border = Theme::from_config(&app.config.theme).unwrap().for_depth(depth).border;
+
+        app.next_theme();
+        assert_eq!(app.theme.name, "light");
+        assert_eq!(app.theme.border, border);
+        assert_eq!(app.theme.own_bg, Theme::by_name("light").unwrap().for_depth(depth).own_bg);
+        app.next_theme();
+        assert_eq!(app.theme.border, border);
+    }
+
+    #[test]
+    fn switching_rooms_stops_the_old_chat() {
+        let runtime = Runtime::new().unwrap();
+        let (mut app, _ws) = offline_app(&runtime);
+        app.session.token = Some("token".to_string()); // Chat retries until stopped
+        app.room_names  = vec!["lobby".to_string(), "dev".to_string()];
+        app.room_hashes = vec!["h1".to_string(), "h2".to_string()];
+
+        app.enter_room();
+        app.receive_message(ChatMessage::new("h1", "ali", "hi".to_string()));
+        app.chat_scroll_index = 3;
+        assert_eq!(app.messages.len(), 1);
+
+        app.room_index = 1;
+        app.enter_room();
+        assert_eq!(app.current_room.as_deref(), Some("h2"));
+        assert!(app.messages.is_empty());
+        assert_eq!(app.chat_scroll_index, 0);
+
+        // Old room's task ends instead of holding on to the channels
+        let reply = app.from_session.recv_timeout(Duration::from_secs(10)).unwrap();
+        assert!(matches!(reply, SessionReply::ChatClosed(Ok(()))));
+
+        // Late frames of the old room don't leak into the new one
+        app.receive_message(ChatMessage::new("h1", "ali", "late".to_string()));
+        app.receive_message(ChatMessage::new("h2", "bo", "hello".to_string()));
+        assert_eq!(app.messages.len(), 1);
+        assert_eq!(app.messages[0].body, "hello");
+    }
+
+    #[test]
+    fn logging_out_outside_a_room_leaves_next_chat_alone() {
+        let runtime = Runtime::new().unwrap();
+        let (mut app, _ws) = offline_app(&runtime);
+        app.session.token = Some("token".to_string());
+        app.handle_event(AppEvent::Session(SessionReply::LoggedOut(Ok(String::new())))).unwrap();
+        assert!(app.chat_stop.is_none());
+
+        // Next session's chat keeps trying instead of reading a stale stop
+        app.session.token = Some("token".to_string());
+        app.room_names  = vec!["lobby".to_string()];
+        app.room_hashes = vec!["h1".to_string()];
+        app.enter_room();
+        std::thread::sleep(Duration::from_millis(200));
+        assert!(!app.chat_stop.as_ref().unwrap().is_closed());
+        assert!(app.from_session.try_recv().is_err());
+    }
+
+    #[test]
+    fn chat_giving_up_leaves_the_room() {
+        let runtime = Runtime::new().unwrap();
+        let (mut app, _ws) = offline_app(&runtime);
+        app.room_names  = vec!["lobby".to_string()];
+        app.room_hashes = vec!["h1".to_string()];
+
+        // Without a token the chat task gives up right away
+        app.enter_room();
+        let reply = app.from_session.recv_timeout(Duration::from_secs(10)).unwrap();
+        app.handle_event(AppEvent::Session(reply)).unwrap();
+        assert!(app.current_room.is_none());
+        assert!(app.chat_stop.is_none());
+        assert!(app.to_ws.is_none());
+        assert!(matches!(app.notices.latest().unwrap().severity, Severity::Warning));
+    }
+
+    #[test]
+    fn logout_from_account_menu_returns_to_form_choose() {
+        let runtime = Runtime::new().unwrap();
+        let (mut app, _ws) = offline_app(&runtime);
+        let key = |app: &mut App, code: KeyCode| {
+            logics::key_bindings(app, KeyEvent::from(code)).unwrap();
+        };
+        app.session.token     = Some("token".to_string());
+        app.session.show_name = Some("ali".to_string());
+        app.current_room      = Some("h1".to_string());
+
+        key(&mut app, KeyCode::Char('a'));
+        assert!(matches!(app.selected_screen, Screen::Account));
+        key(&mut app, KeyCode::Down);
+        key(&mut app, KeyCode::Enter);
+        assert!(matches!(app.form.kind, Forms::Repass));
+
+        // Mismatched passwords never reach the server
+        app.form.set_value(0, "old");
+        app.form.set_value(1, "secret123");
+        app.form.set_value(2, "other");
+        app.submit_form();
+        assert_eq!(app.pending, 0);
+        assert_eq!(app.form.error(2).unwrap(), "Doesn't match");
+
+        key(&mut app, KeyCode::Esc);
+        key(&mut app, KeyCode::Char('a'));
+        key(&mut app, KeyCode::Down);
+        key(&mut app, KeyCode::Enter);
+        assert!(matches!(app.confirm, Some(Confirm::Logout)));
+
+        // Token that couldn't be removed from disk keeps us logged in
+        let reply = SessionReply::LoggedOut(Err(SessionError::Store("read-only".to_string())));
+        app.handle_event(AppEvent::Session(reply)).unwrap();
+        assert!(app.session.token.is_some());
+        assert!(matches!(app.notices.latest().unwrap().severity, Severity::Error));
+
+        // Server being unreachable doesn't
+        let reply = SessionReply::LoggedOut(Err(SessionError::MissingToken));
+        app.handle_event(AppEvent::Session(reply)).unwrap();
+        assert!(matches!(app.selected_screen, Screen::FormChoose));
+        assert!(app.session.token.is_none());
+        assert!(app.current_room.is_none());
+    }
+
+    #[test]
+    fn recovery_asks_question_before_reset() {
+        let runtime = Runtime::new().unwrap();
+        let (mut app, _ws) = offline_app(&runtime);
+        let mut terminal = Terminal::new(TestBackend::new(100, 30)).unwrap();
+        app.selected_screen = Screen::FormChoose;
+        app.form = choose_form();
+        app.form.selected_input = 2;
+        app.jump2form();
+        assert!(matches!(app.form.kind, Forms::Recovery));
+
+        // Only the username is asked for at first
+        app.submit_form();
+        assert_eq!(app.form.shown_error(0).unwrap(), "Required");
+        assert_eq!(app.form.error(2), None);
+
+        app.form.set_value(0, "ali");
+        let question = SessionReply::Question(Ok(("ali".to_string(), "First pet?".to_string())));
+        app.handle_event(AppEvent::Session(question)).unwrap();
+        terminal.draw(|frame| draw_ui(frame, &app, &app.theme)).unwrap();
+        assert!(buffer_text(&terminal).contains("Q: First pet?"));
+
+        // Question is known, so this only checks the fields
+        app.form.set_value(1, "rex");
+        app.form.set_value(2, "secret123");
+        app.submit_form();
+        assert_eq!(app.form.error(3).unwrap(), "Doesn't match");
+        assert_eq!(app.pending, 0);
+        terminal.draw(|frame| draw_ui(frame, &app, &app.theme)).unwrap();
+        assert!(buffer_text(&terminal).contains("Doesn't match"));
+
+        app.handle_event(AppEvent::Session(SessionReply::Recovered(Ok("ali".to_string())))).unwrap();
+        assert!(matches!(app.form.kind, Forms::SignIn));
+        assert_eq!(app.form.inputs[0].borrow()[0], "ali");
+    }
+
+    #[test]
+    fn recovery_form_is_driven_by_keys() {
+        let runtime = Runtime::new().unwrap();
+        let (mut app, _ws) = offline_app(&runtime);
+        app.session.host = runtime.block_on(canned_server(vec![
+            ("/auth/uexist",   r#"{"status":"ok"}"#),
+            ("/auth/question", r#"{"status":"ok","data":{"related_question":"First pet?"}}"#),
+            ("/auth/recover",  r#"{"status":"ok"}"#),
+        ]));
+        let press = |app: &mut App, code: KeyCode| {
+            logics::key_bindings(app, KeyEvent::from(code)).unwrap();
+            // run_app links the selected field before every frame
+            if matches!(app.selected_screen, Screen::Form) {
                 app.update_input();
-                app.receive_message();
             }
-            _                  => {app.update_input();},
-        }
+        };
+        let type_in = |app: &mut App, text: &str| {
+            text.chars().for_each(|c| press(app, KeyCode::Char(c)));
+            press(app, KeyCode::Enter);
+        };
+        let answer = |app: &mut App| {
+            let reply = app.from_session.recv_timeout(Duration::from_secs(10)).unwrap();
+            app.handle_event(AppEvent::Session(reply)).unwrap();
+        };
+        app.selected_screen = Screen::FormChoose;
+        app.form = choose_form();
+
+        press(&mut app, KeyCode::Down);
+        press(&mut app, KeyCode::Down);
+        press(&mut app, KeyCode::Enter);
+        assert!(matches!(app.form.kind, Forms::Recovery));
+
+        // Username is all there is for now, Tab stays on it and Enter submits it
+        press(&mut app, KeyCode::Tab);
+        press(&mut app, KeyCode::Enter);
+        type_in(&mut app, "ali");
+        assert_eq!(app.pending, 1);
+        answer(&mut app);
+        assert_eq!(app.form.note.as_deref(), Some("Q: First pet?"));
+        assert_eq!(app.form.selected_input, 1);
+
+        press(&mut app, KeyCode::Enter);
+        type_in(&mut app, "rex");
+        type_in(&mut app, "secret123");
+        type_in(&mut app, "secret123");
+        assert_eq!(app.pending, 1);
+        answer(&mut app);
+        assert!(matches!(app.form.kind, Forms::SignIn));
+        assert_eq!(app.form.value(0), "ali");
+    }
+
+    #[test]
+    fn stale_recovery_replies_are_dropped() {
+        let runtime = Runtime::new().unwrap();
+        let (mut app, _ws) = offline_app(&runtime);
+        app.selected_screen = Screen::FormChoose;
+        app.form = choose_form();
+        app.form.selected_input = 2;
+        app.jump2form();
+        app.form.set_value(0, "bo");
+
+        // Asked for ali, but the username changed since
+        let question = SessionReply::Question(Ok(("ali".to_string(), "First pet?".to_string())));
+        app.handle_event(AppEvent::Session(question)).unwrap();
+        assert_eq!(app.form.active, 1);
+        assert!(app.recovery.is_none());
+
+        // Form was left before the reset came back
+        app.go_home();
+        app.handle_event(AppEvent::Session(SessionReply::Recovered(Ok("bo".to_string())))).unwrap();
+        assert!(!matches!(app.form.kind, Forms::SignIn));
+    }
 
-        terminal.draw(|frame| draw_ui(frame, app))?;
-        if let Event::Key(key) = event::read()? {
-            if key.kind == event::KeyEventKind::Release {continue}
-            let _res = logics::key_bindings(app, key);
+    #[test]
+    fn sign_up_name_is_checked_after_typing_pauses() {
+        let runtime = Runtime::new().unwrap();
+        let (mut app, _ws) = offline_app(&runtime);
+        app.selected_screen = Screen::Form;
+        app.form = Form::new(Some(Forms::SignUp), None);
+        app.update_input();
+        app.mode = Modes::Insert;
+        for c in "alice".chars() {
+            logics::key_bindings(&mut app, KeyEvent::from(KeyCode::Char(c))).unwrap();
         }
+
+        app.handle_event(AppEvent::Tick).unwrap();
+        assert_eq!(app.pending, 0);
+        app.handle_event(AppEvent::Tick).unwrap();
+        assert_eq!(app.pending, 1);
+        assert!(app.name_edited_at.is_none());
+
+        let reply = SessionReply::NameTaken(Ok(("alice".to_string(), true)));
+        app.handle_event(AppEvent::Session(reply)).unwrap();
+        assert_eq!(app.form.shown_error(0).unwrap(), "Already taken");
+    }
+
+    #[test]
+    fn room_edit_is_prefilled_and_close_asks_first() {
+        let runtime = Runtime::new().unwrap();
+        let (mut app, _ws) = offline_app(&runtime);
+        let press = |app: &mut App, c: char| {
+            logics::key_bindings(app, KeyEvent::from(KeyCode::Char(c))).unwrap();
+        };
+        app.selected_block = Block::Rooms;
+        app.room_names  = vec!["lobby".to_string(), "dev".to_string()];
+        app.room_hashes = vec!["h1".to_string(), "h2".to_string()];
+        app.room_visibility = vec![Visibility::Public, Visibility::Private];
+        app.room_index  = 1;
+
+        press(&mut app, 'e');
+        assert!(matches!(app.form.kind, Forms::RoomEdit));
+        assert_eq!(app.form.inputs[0].borrow()[0], "dev");
+        assert_eq!(app.room_in_edit.as_deref(), Some("h2"));
+        assert_eq!(app.form.value(1), "Private");
+
+        // Unchanged name goes straight back without a server call
+        app.session.token = Some("token".to_string());
+        app.submit_form();
+        assert!(matches!(app.selected_screen, Screen::Main));
+        assert_eq!(app.pending, 0);
+
+        press(&mut app, 'x');
+        assert!(matches!(app.confirm, Some(Confirm::CloseRoom { ref hash, .. }) if hash == "h2"));
+        press(&mut app, 'n');
+        assert!(app.confirm.is_none());
+        assert_eq!(app.pending, 0);
+
+        // Closing the last room leaves nothing to enter
+        app.room_names.clear();
+        app.room_hashes.clear();
+        app.room_index = 0;
+        logics::key_bindings(&mut app, KeyEvent::from(KeyCode::Enter)).unwrap();
+        assert!(app.current_room.is_none());
+        assert_eq!(app.notices.latest().unwrap().text, "No room to enter");
+    }
+
+    #[test]
+    fn room_selection_stays_in_the_refreshed_list() {
+        let runtime = Runtime::new().unwrap();
+        let (mut app, _ws) = offline_app(&runtime);
+        let listed = |names: &[&str]| SessionReply::Rooms(Ok(RoomList {
+            names:      names.iter().map(|n| n.to_string()).collect(),
+            hashes:     names.iter().map(|n| format!("#{n}")).collect(),
+            visibility: vec![Visibility::Public; names.len()],
+            problem:    None,
+        }));
+
+        app.room_index = 1;
+        app.handle_event(AppEvent::Session(listed(&["lobby", "dev", "ops"]))).unwrap();
+        assert_eq!(app.room_index, 1);
+
+        app.room_index = 2;
+        app.handle_event(AppEvent::Session(listed(&["lobby"]))).unwrap();
+        assert_eq!(app.room_index, 0);
+        logics::key_bindings(&mut app, KeyEvent::from(KeyCode::Enter)).unwrap();
+        assert_eq!(app.current_room.as_deref(), Some("#lobby"));
+
+        app.handle_event(AppEvent::Session(listed(&[]))).unwrap();
+        assert_eq!(app.room_index, 0);
     }
-    Ok(())
 }
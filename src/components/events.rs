@@ -0,0 +1,57 @@
+use crate::server_talk::{
+    chat::{ChatMessage, ConnState},
+    errors::SessionError,
+    session::{LoginResponse, RoomList},
+};
+use super::notices::Severity;
+
+use crossbeam_channel::{unbounded, Receiver};
+use crossterm::event::{self, Event};
+use std::{
+    thread,
+    time::Duration,
+};
+
+
+// How often the UI is redrawn even if nothing happens
+pub const TICK_RATE: Duration = Duration::from_millis(250);
+
+pub enum AppEvent {
+    Input(Event),    // Anything crossterm reports (keys, resizes, ...)
+    Ws(ChatMessage), // A message coming from chat_connect task
+    Conn(ConnState),
+    Notice(Severity, String),
+    Session(SessionReply),
+    Tick,
+}
+
+// What comes back once a Session call spawned on the runtime is done
+pub enum SessionReply {
+    Ping(Result<String, SessionError>),
+    SignUp(Result<String, SessionError>),
+    LogIn(Result<LoginResponse, SessionError>),
+    Rooms(Result<RoomList, SessionError>),
+    RoomBuilt(Result<String, SessionError>),
+    RoomRenamed(Result<String, SessionError>),
+    RoomClosed(Result<String, SessionError>),  // Hash of the closed room
+    Renamed(Result<String, SessionError>),     // New display name
+    Repassed(Result<String, SessionError>),
+    LoggedOut(Result<String, SessionError>),
+    Question(Result<(String, String), SessionError>), // Username and its question
+    Recovered(Result<String, SessionError>),
+    NameTaken(Result<(String, bool), SessionError>), // Username asked about and whether it's in use
+    ChatClosed(Result<(), SessionError>),
+}
+
+// Moves blocking terminal reads off the render loop
+pub fn spawn_input_reader() -> Receiver<Event> {
+    let (tx, rx) = unbounded::<Event>();
+    thread::spawn(move || {
+        while let Ok(ev) = event::read() {
+            if tx.send(ev).is_err() {
+                break
+            }
+        }
+    });
+    rx
+}
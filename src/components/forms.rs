@@ -1,27 +1,149 @@
 use super::states::Forms;
+use crate::server_talk::session::Visibility;
 use std::{
-    usize,
     rc::Rc,
     cell::RefCell,
 };
+use zeroize::Zeroize;
 
 
+// How a field is edited and drawn
+#[derive(Clone, Debug, PartialEq)]
+pub enum InputKind {
+    Text,
+    Secret,              // Drawn as bullets unless revealed
+    Boolean,             // "true" / "false", flipped instead of typed
+    Choice(Vec<String>), // One of these, cycled through
+}
+
+// What a field's value has to look like, checked in order
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Rule {
+    Required,
+    Length(usize, usize),  // Chars, both ends included
+    Charset,               // Letters, digits and _ - .
+    Strong,                // 8+ chars with a letter and a digit
+    Matches(usize),        // Same as that field
+    Available,             // Not among the names the server said are taken
+}
+
+impl Rule {
+    fn check(self, form: &Form, value: &str) -> Result<(), String> {
+        let len = value.chars().count();
+        let ok = match self {
+            Rule::Required         => !value.trim().is_empty(),
+            Rule::Length(min, max) => (min..=max).contains(&len),
+            Rule::Charset          => value.chars().all(|c| c.is_alphanumeric() || "_-.".contains(c)),
+            Rule::Strong           => len >= 8
+                && value.chars().any(char::is_alphabetic)
+                && value.chars().any(|c| c.is_ascii_digit()),
+            Rule::Matches(other)   => form.inputs[other].borrow()[0] == value,
+            Rule::Available        => !form.taken.iter().any(|t| t == value),
+        };
+        match (ok, self) {
+            (true, _)                       => Ok(()),
+            (false, Rule::Required)         => Err("Required".to_string()),
+            (false, Rule::Length(min, max)) => Err(format!("Must be {min} to {max} characters")),
+            (false, Rule::Charset)          => Err("Only letters, digits and _ - .".to_string()),
+            (false, Rule::Strong)           => Err("At least 8 characters with a letter and a digit".to_string()),
+            (false, Rule::Matches(_))       => Err("Doesn't match".to_string()),
+            (false, Rule::Available)        => Err("Already taken".to_string()),
+        }
+    }
+}
+
+// One field as the renderer and the validators see it
+#[derive(Clone, Debug)]
+pub struct FieldSpec {
+    pub label:       &'static str,
+    pub kind:        InputKind,
+    pub placeholder: &'static str, // Shown while empty
+    pub rules:       Vec<Rule>,
+    pub fixed:       bool,         // Shown but can't be changed
+}
+
+// Everything needed to draw and check a form, adding a form only means adding one here
+#[derive(Clone, Debug)]
+pub struct FormSpec {
+    pub title:  &'static str,
+    pub fields: Vec<FieldSpec>,
+}
+
+fn field(label: &'static str, kind: InputKind, placeholder: &'static str, rules: &[Rule]) -> FieldSpec {
+    FieldSpec { label, kind, placeholder, rules: rules.to_vec(), fixed: false }
+}
+
+impl FormSpec {
+    pub fn of(kind: &Forms) -> Self {
+        use InputKind::*;
+        use Rule::*;
+        let name = [Required, Length(3, 32), Charset];
+        let visibility = || Choice(Visibility::ALL.iter().map(|v| v.label().to_string()).collect());
+
+        let (title, fields) = match kind {
+            Forms::SignUp      => ("Sign Up", vec![
+                field("Username:", Text,    "3 to 32 letters or digits", &[&name[..], &[Available]].concat()),
+                field("Password:", Secret,  "8+ with a letter and a digit", &[Required, Strong]),
+                field("Confirm:",  Secret,  "Password again", &[Matches(1)]),
+                field("Question:", Text,    "Asked if you forget the password", &[Required, Length(3, 200)]),
+                field("Answer:",   Text,    "", &[Required]),
+            ]),
+            Forms::SignIn      => ("Sign In", vec![
+                field("Username:", Text,    "", &[Required]),
+                field("Password:", Secret,  "", &[Required]),
+                field("Remember me:", Boolean, "", &[]),
+            ]),
+            Forms::RoomCreator => ("Room Creation", vec![
+                field("Roomname:", Text,    "", &[Required, Length(1, 64)]),
+                field("Visibility:", visibility(), "", &[]),
+            ]),
+            Forms::RoomEdit    => ("Room Edit", vec![
+                field("Roomname:", Text,    "", &[Required, Length(1, 64)]),
+                FieldSpec { fixed: true, ..field("Visibility:", visibility(), "", &[]) },
+            ]),
+            Forms::Rename      => ("Display Name", vec![
+                field("New name:", Text,    "3 to 32 letters or digits", &name),
+            ]),
+            Forms::Repass      => ("Change Password", vec![
+                field("Current:",  Secret,  "", &[Required]),
+                field("New:",      Secret,  "8+ with a letter and a digit", &[Required, Strong]),
+                field("Confirm:",  Secret,  "New password again", &[Matches(1)]),
+            ]),
+            Forms::Recovery    => ("Recover Account", vec![
+                field("Username:", Text,    "", &[Required]),
+                field("Answer:",   Text,    "", &[Required]),
+                field("New:",      Secret,  "8+ with a letter and a digit", &[Required, Strong]),
+                field("Confirm:",  Secret,  "New password again", &[Matches(2)]),
+            ]),
+            Forms::Typing      => ("", vec![field("", Text, "", &[])]),
+        };
+        Self { title, fields }
+    }
+}
+
 pub struct Form {
     pub kind:           Forms,
-    pub inputs:         Vec<Rc<RefCell<Vec<String>>>>,
+    pub spec:           FormSpec,
+    pub inputs:         Vec<Rc<RefCell<Vec<String>>>>, // One per field of spec
+    pub active:         usize,            // Only this many leading fields are in play
+    pub attempted:      bool,             // Submit was tried, empty fields show their errors too
+    pub taken:          Vec<String>,      // Names the server reported as in use
+    pub note:           Option<String>,   // Shown under the form, set by whoever opened it
     pub options:        Vec<String>,
     pub selected_input: usize,
-    pub is_public:      bool,
+    pub revealed_until: Option<usize>,    // Secrets show in clear until this tick
 }
 
 impl Form {
-    pub fn new(k: Option<Forms>, n_inputs: Option<usize>, options: Option<Vec<String>>) -> Self {
-        let n = match n_inputs {
+    pub fn new(k: Option<Forms>, options: Option<Vec<String>>) -> Self {
+        let kind = match k {
             Some(value) => value,
-            None        => 1,
+            None        => Forms::Typing,
         };
-        let inps = (0..n)
-            .map(|_| Rc::new(RefCell::new(vec![String::from("")])))
+        let spec = FormSpec::of(&kind);
+        let inps: Vec<_> = spec.fields
+            .iter()
+            .map(|f| Rc::new(RefCell::new(vec![initial_value(&f.kind)])))
             .collect();
 
         let opts: Vec<String> = match options {
@@ -30,18 +152,147 @@ impl Form {
         };
 
         Self {
-            kind:           match k {
-                Some(value) => value,
-                None        => Forms::Typing,
-            },
+            kind,
+            active:            inps.len(),
+            spec,
             inputs:            inps,
+            attempted:         false,
+            taken:             Vec::new(),
+            note:              None,
             options:           opts,
             selected_input:    0,
-            is_public:         true,
+            revealed_until:    None,
+        }
+    }
+
+    pub fn value(&self, i: usize) -> String {
+        self.inputs[i].borrow()[0].clone()
+    }
+
+    pub fn set_value(&self, i: usize, value: impl Into<String>) {
+        self.inputs[i].borrow_mut()[0] = value.into();
+    }
+
+    // First broken rule of a field, fields out of play are always fine.
+    // Checked in place so secrets aren't copied around on every frame
+    pub fn error(&self, i: usize) -> Option<String> {
+        if i >= self.active {
+            return None
+        }
+        let input = self.inputs[i].borrow();
+        self.spec.fields[i].rules.iter().find_map(|rule| rule.check(self, &input[0]).err())
+    }
+
+    pub fn is_valid(&self) -> bool {
+        (0..self.inputs.len()).all(|i| self.error(i).is_none())
+    }
+
+    // Errors stay hidden on untouched fields until a submit is tried
+    pub fn shown_error(&self, i: usize) -> Option<String> {
+        match self.attempted || !self.inputs[i].borrow()[0].is_empty() {
+            true  => self.error(i),
+            false => None,
+        }
+    }
+
+    pub fn selected_field(&self) -> &FieldSpec {
+        &self.spec.fields[self.selected_input]
+    }
+
+    // Fields past active aren't in play yet, so the form ends there
+    pub fn is_last_field(&self) -> bool {
+        self.selected_input + 1 >= self.active
+    }
+
+    // Moves a choice on, other kinds are typed into
+    pub fn cycle_selected(&mut self) {
+        let mut input = self.inputs[self.selected_input].borrow_mut();
+        let next = match &self.spec.fields[self.selected_input].kind {
+            InputKind::Boolean       => (input[0] != "true").to_string(),
+            InputKind::Choice(items) => {
+                let at = items.iter().position(|c| *c == input[0]).map_or(0, |i| (i + 1) % items.len());
+                items[at].clone()
+            },
+            _                        => return,
+        };
+        input[0] = next;
+    }
+
+    // Wipes typed secrets from memory, not only from the screen
+    pub fn clear_secrets(&mut self) {
+        for (input, field) in self.inputs.iter().zip(&self.spec.fields) {
+            if field.kind == InputKind::Secret {
+                let mut lines = input.borrow_mut();
+                lines.iter_mut().for_each(Zeroize::zeroize);
+                *lines = vec![String::new()];
+            }
         }
+        self.revealed_until = None;
     }
+}
+
+fn initial_value(kind: &InputKind) -> String {
+    match kind {
+        InputKind::Boolean       => "true".to_string(),
+        InputKind::Choice(items) => items.first().cloned().unwrap_or_default(),
+        _                        => String::new(),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn secrets_are_wiped_and_choices_cycle() {
+        let mut form = Form::new(Some(Forms::SignIn), None);
+        form.set_value(0, "ali");
+        form.set_value(1, "hunter2");
+        form.clear_secrets();
+        assert_eq!(form.value(0), "ali");
+        assert_eq!(form.value(1), "");
+
+        let mut form = Form::new(Some(Forms::RoomCreator), None);
+        assert_eq!(form.value(1), "Public");
+        form.selected_input = 1;
+        form.cycle_selected();
+        assert_eq!(form.value(1), "Private");
+        form.cycle_selected();
+        assert_eq!(form.value(1), "Public");
+
+        let mut form = Form::new(Some(Forms::SignIn), None);
+        assert_eq!(form.value(2), "true");
+        form.selected_input = 2;
+        form.cycle_selected();
+        assert_eq!(form.value(2), "false");
+    }
+
+    #[test]
+    fn rules_report_first_problem_per_field() {
+        let form = Form::new(Some(Forms::SignUp), None);
+        assert!(!form.is_valid());
+        assert_eq!(form.shown_error(0), None);
+
+        form.set_value(0, "a b");
+        assert_eq!(form.error(0).unwrap(), "Only letters, digits and _ - .");
+        form.set_value(1, "short1");
+        assert!(form.error(1).unwrap().contains("8 characters"));
+        form.set_value(2, "short2");
+        assert_eq!(form.error(2).unwrap(), "Doesn't match");
+
+        let mut form = Form::new(Some(Forms::SignUp), None);
+        for (i, value) in ["ali", "secret123", "secret123", "First pet?", "rex"].iter().enumerate() {
+            form.set_value(i, *value);
+        }
+        assert!(form.is_valid());
+        form.taken.push("ali".to_string());
+        assert_eq!(form.error(0).unwrap(), "Already taken");
 
-    pub fn switch_pub(&mut self) {
-        self.is_public = !self.is_public;
+        // Fields out of play don't count
+        let mut form = Form::new(Some(Forms::Recovery), None);
+        form.active = 1;
+        form.set_value(0, "ali");
+        assert!(form.is_valid());
     }
 }
@@ -2,164 +2,191 @@ use super::forms::Form;
 use super::states::{Block, Modes, Screen, Forms};
 use super::app::{
     App,
+    ACCOUNT_MENU,
     hover_over,
 };
+use crate::settings::keymap::{Action, Context, Key, Lookup};
 
 use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
 use std::io;
 
 
-pub fn key_bindings(app: &mut App, e: KeyEvent) -> io::Result<()> {
+// Keymap sections that apply right now, most specific first
+pub fn active_contexts(app: &App) -> Vec<Context> {
     match app.mode {
+        Modes::Insert => vec![Context::Insert],
         Modes::Normal => {
+            let screen = match app.selected_screen {
+                Screen::Main => match app.selected_block {
+                    Block::Rooms  => vec![Context::Rooms, Context::Main],
+                    Block::Chat   => vec![Context::Chat, Context::Main],
+                    Block::Typing => vec![Context::Main],
+                },
+                Screen::FormChoose => vec![Context::Choose],
+                Screen::Form       => vec![Context::Form],
+                Screen::Profiles   => vec![Context::Profiles],
+                Screen::Account    => vec![Context::Account],
+            };
+            [screen, vec![Context::Global]].concat()
+        },
+    }
+}
 
-            match e.code {
-                KeyCode::Char('q')  => {
-                    app.exit = true;
+pub fn key_bindings(app: &mut App, e: KeyEvent) -> io::Result<()> {
+    // Help sits on top of everything, any key just closes it
+    if app.show_help {
+        app.show_help = false;
+        return Ok(())
+    }
+    // So does a yes / no question, only y or Enter says yes
+    if app.confirm.is_some() {
+        let yes = matches!(e.code, KeyCode::Char('y') | KeyCode::Char('Y') | KeyCode::Enter);
+        app.answer_confirm(yes);
+        return Ok(())
+    }
+
+    let key      = Key::from(e);
+    let contexts = active_contexts(app);
+    app.pending_keys.push(key);
+
+    let mut found = app.config.keymap.lookup(&contexts, &app.pending_keys);
+    // A dead end sequence is dropped, its last key gets a chance on its own
+    if found == Lookup::Unbound && app.pending_keys.len() > 1 {
+        app.pending_keys = vec![key];
+        found = app.config.keymap.lookup(&contexts, &app.pending_keys);
+    }
+
+    match found {
+        Lookup::Prefix         => {},
+        Lookup::Action(action) => {
+            app.pending_keys.clear();
+            perform(app, action);
+        },
+        Lookup::Unbound        => {
+            app.pending_keys.clear();
+            match (&app.mode, e.code) {
+                // Whatever isn't bound gets typed
+                (Modes::Insert, KeyCode::Char(to_insert)) if !e.modifiers.contains(KeyModifiers::CONTROL) => {
+                    app.insert_char(to_insert);
                 },
-                _ => {}
+                _ => {},
             }
+        },
+    }
 
-            match app.selected_screen {
-                Screen::Main => {
-                    match e.code {
-
-                        KeyCode::Tab => {
-                            match app.selected_block {
-                                Block::Rooms  => {app.selected_block = Block::Chat},
-                                Block::Chat   => {app.selected_block = Block::Typing},
-                                Block::Typing => {app.selected_block = Block::Rooms},
-                            }
-                        },
-
-                        KeyCode::BackTab => {
-                            match app.selected_block {
-                                Block::Rooms  => {app.selected_block = Block::Typing},
-                                Block::Chat   => {app.selected_block = Block::Rooms},
-                                Block::Typing => {app.selected_block = Block::Chat},
-                            }
-                        },
-
-                        KeyCode::Enter => match app.selected_block {
-                            Block::Typing => {
-                                app.mode = Modes::Insert;
-                                app.set_curser();
-                            },
-                            Block::Rooms => {
-                                app.enter_room();
-                            }
-                            _ => {}
-                        },
-
-                        _ => {}
-                    }
-
-                    match app.selected_block {
-                        Block::Rooms => match e.code {
-                            KeyCode::Up        => {
-                                hover_over(app.room_names.len() - 1, &mut app.room_index, false);
-                            },
-                            KeyCode::Down      => {
-                                hover_over(app.room_names.len() - 1, &mut app.room_index, true);
-                            },
-                            KeyCode::Char('c') => {
-                                app.selected_screen = Screen::Form;
-                                app.form = Form::new(Some(Forms::RoomCreator), Some(2), None);
-                            },
-                            KeyCode::Char('e') => {
-                                app.selected_screen = Screen::Form;
-                                app.form = Form::new(Some(Forms::RoomEdit), Some(2), None);
-                            },
-                            _ => {}
-                        },
-                        _ => {},
-                    }
-                },
+    if matches!((&app.mode, &app.selected_screen), (Modes::Insert, Screen::Form)) {
+        app.form_edited();
+    }
+    Ok(())
+}
 
-                Screen::Form => match e.code {
-                    KeyCode::Tab => {
-                        hover_over(app.form.inputs.len() - 1, &mut app.form.selected_input, false);
-                    },
-
-                    KeyCode::BackTab => {
-                        hover_over(app.form.inputs.len() - 1, &mut app.form.selected_input, true);
-                    },
-
-                    KeyCode::Enter => match app.form.kind {
-                        Forms::RoomCreator | Forms::RoomEdit => {
-                            app.toggle_form_bool();
-                        },
-                        _ => {
-                            app.mode = Modes::Insert;
-                            app.set_curser();
-                        },
-                    },
-
-                    KeyCode::Char(' ') => {
-                        app.submit_form();
-                    },
-
-                    _ => {},
-                },
+fn perform(app: &mut App, action: Action) {
+    match action {
+        Action::Quit => {
+            app.exit = true;
+        },
 
-                Screen::FormChoose => match e.code {
-                    KeyCode::Up => {
-                        hover_over(app.form.options.len() - 1, &mut app.form.selected_input, false);
-                    },
+        Action::Help => app.show_help = true,
 
-                    KeyCode::Down => {
-                        hover_over(app.form.options.len() - 1, &mut app.form.selected_input, true);
-                    },
+        Action::NextTheme => app.next_theme(),
 
-                    KeyCode::Enter => {
-                        app.jump2form();
-                    },
+        Action::FocusNext => {
+            match app.selected_block {
+                Block::Rooms  => {app.selected_block = Block::Chat},
+                Block::Chat   => {app.selected_block = Block::Typing},
+                Block::Typing => {app.selected_block = Block::Rooms},
+            }
+        },
 
-                    _ => {},
-                },
+        Action::FocusPrev => {
+            match app.selected_block {
+                Block::Rooms  => {app.selected_block = Block::Typing},
+                Block::Chat   => {app.selected_block = Block::Rooms},
+                Block::Typing => {app.selected_block = Block::Chat},
             }
         },
 
-        Modes::Insert => {
-            match e.modifiers {
+        Action::Activate => match app.selected_block {
+            Block::Typing => {
+                app.mode = Modes::Insert;
+                app.set_curser();
+            },
+            Block::Rooms => {
+                app.enter_room();
+            }
+            _ => {}
+        },
 
-                KeyModifiers::CONTROL => {
-                    match e.code {
-                        KeyCode::Char('w')       => app.delete_word(),
-                        _ => {}
-                    }
-                },
+        Action::OpenProfiles => app.open_profiles(),
 
-                KeyModifiers::SHIFT => {
-                    match e.code {
-                        KeyCode::Right           => app.foreword(),
-                        KeyCode::Left            => app.backword(),
+        Action::OpenAccount => app.open_account(),
 
-                        // Allow uppercase letters to happen
-                        KeyCode::Char(to_insert) => app.insert_char(to_insert),
-                        _ => {}
-                    }
+        Action::SelectPrev | Action::SelectNext => {
+            let go_next = action == Action::SelectNext;
+            match app.selected_screen {
+                Screen::Main => {
+                    hover_over(app.room_names.len().saturating_sub(1), &mut app.room_index, go_next);
+                },
+                Screen::FormChoose => {
+                    hover_over(app.form.options.len() - 1, &mut app.form.selected_input, go_next);
+                },
+                Screen::Profiles => {
+                    hover_over(app.profiles.list.len() - 1, &mut app.profile_hover, go_next);
                 },
+                Screen::Account => {
+                    hover_over(ACCOUNT_MENU.len() - 1, &mut app.account_hover, go_next);
+                },
+                _ => {},
+            }
+        },
 
-                _ => {
-                    match e.code {
-                        KeyCode::Char(to_insert) => app.insert_char(to_insert),
-                        KeyCode::Right           => app.move_cursor_right(),
-                        KeyCode::Left            => app.move_cursor_left(),
-                        KeyCode::Up              => app.go_top_line(),
-                        KeyCode::Down            => app.go_bottom_line(),
-                        KeyCode::Backspace       => app.delete_char(),
-                        KeyCode::Esc             => app.mode = Modes::Normal,
-                        KeyCode::Enter           => {
-                            app.send_message();
-                            app.submit_message();
-                        },
-                        _ => {}
-                    }
-                }
+        Action::Confirm => match app.selected_screen {
+            Screen::FormChoose => app.jump2form(),
+            Screen::Profiles   => app.switch_profile(app.profile_hover),
+            Screen::Account    => app.account_action(),
+            _                  => {},
+        },
 
-            }
-        }
+        Action::Back => app.go_home(),
+
+        Action::CreateRoom => {
+            app.selected_screen = Screen::Form;
+            app.form = Form::new(Some(Forms::RoomCreator), None);
+        },
+
+        Action::EditRoom  => app.edit_room(),
+        Action::CloseRoom => app.ask_close_room(),
+
+        Action::ScrollUp     => app.scroll_chat_up(1),
+        Action::ScrollDown   => app.scroll_chat_down(1),
+        Action::PageUp       => app.scroll_chat_up(app.chat_page()),
+        Action::PageDown     => app.scroll_chat_down(app.chat_page()),
+        Action::ScrollTop    => app.scroll_chat_top(),
+        Action::ScrollBottom => app.scroll_chat_bottom(),
+
+        Action::FieldNext => {
+            hover_over(app.form.active.saturating_sub(1), &mut app.form.selected_input, true);
+        },
+
+        Action::FieldPrev => {
+            hover_over(app.form.active.saturating_sub(1), &mut app.form.selected_input, false);
+        },
+
+        Action::EditField => app.edit_field(),
+
+        Action::Reveal => app.reveal_secrets(),
+
+        Action::SubmitForm => app.submit_form(),
+
+        Action::CursorLeft  => app.move_cursor_visual(false),
+        Action::CursorRight => app.move_cursor_visual(true),
+        Action::WordLeft    => app.word_visual(false),
+        Action::WordRight   => app.word_visual(true),
+        Action::LineUp      => app.go_top_line(),
+        Action::LineDown    => app.go_bottom_line(),
+        Action::DeleteChar  => app.delete_char(),
+        Action::DeleteWord  => app.delete_word(),
+        Action::NormalMode  => app.mode = Modes::Normal,
+        Action::Send        => app.submit_message(),
     }
-    Ok(())
 }
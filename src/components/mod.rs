@@ -1,4 +1,6 @@
 pub mod app;
+pub mod events;
 pub mod logics;
+pub mod notices;
 pub mod states;
 pub mod forms;
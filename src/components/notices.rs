@@ -0,0 +1,93 @@
+use std::collections::VecDeque;
+
+
+// Oldest ones are dropped past this
+const MAX_NOTICES: usize = 5;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Severity {
+    Info,
+    Success,
+    Warning,
+    Error,
+}
+
+impl Severity {
+    // How long it stays up, in ticks of TICK_RATE
+    fn lifetime(self) -> usize {
+        match self {
+            Severity::Info    => 16,
+            Severity::Success => 16,
+            Severity::Warning => 32,
+            Severity::Error   => 40,
+        }
+    }
+
+    // Warnings and errors pop up as toasts, the rest only reach the status bar
+    pub fn is_toast(self) -> bool {
+        matches!(self, Severity::Warning | Severity::Error)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Notice {
+    pub text:     String,
+    pub severity: Severity,
+    until:        usize, // Tick it expires on
+}
+
+#[derive(Default)]
+pub struct Notices {
+    queue: VecDeque<Notice>, // Oldest first
+}
+
+impl Notices {
+    pub fn push(&mut self, severity: Severity, text: impl Into<String>, now: usize) {
+        let text = text.into();
+        // Same thing reported again just stays up longer
+        self.queue.retain(|n| n.text != text);
+        self.queue.push_back(Notice { text, severity, until: now + severity.lifetime() });
+        while self.queue.len() > MAX_NOTICES {
+            self.queue.pop_front();
+        }
+    }
+
+    pub fn expire(&mut self, now: usize) {
+        self.queue.retain(|n| n.until > now);
+    }
+
+    pub fn latest(&self) -> Option<&Notice> {
+        self.queue.back()
+    }
+
+    pub fn toasts(&self) -> impl Iterator<Item = &Notice> {
+        self.queue.iter().filter(|n| n.severity.is_toast())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn notices_expire_and_repeats_are_merged() {
+        let mut notices = Notices::default();
+        notices.push(Severity::Success, "Room created", 0);
+        notices.push(Severity::Error, "Wrong password", 0);
+        notices.push(Severity::Error, "Wrong password", 10);
+        assert_eq!(notices.toasts().count(), 1);
+        assert_eq!(notices.latest().unwrap().text, "Wrong password");
+
+        notices.expire(20);
+        assert_eq!(notices.queue.len(), 1);
+        notices.expire(50);
+        assert!(notices.latest().is_none());
+
+        for i in 0..10 {
+            notices.push(Severity::Info, format!("{i}"), 0);
+        }
+        assert_eq!(notices.queue.len(), MAX_NOTICES);
+        assert_eq!(notices.latest().unwrap().text, "9");
+    }
+}
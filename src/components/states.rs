@@ -2,6 +2,8 @@ pub enum Screen {
     Main,
     FormChoose,
     Form,
+    Profiles,
+    Account,
 }
 
 pub enum Forms {
@@ -9,6 +11,9 @@ pub enum Forms {
     SignIn,
     RoomCreator,
     RoomEdit,
+    Rename,
+    Repass,
+    Recovery,
     Typing,
 }
 
@@ -18,6 +23,12 @@ pub enum Block {
     Typing,
 }
 
+// Asked before doing something that can't be undone
+pub enum Confirm {
+    CloseRoom { hash: String, name: String },
+    Logout,
+}
+
 pub enum Modes {
     Normal,
     Insert,
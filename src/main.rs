@@ -1,5 +1,6 @@
 mod components;
 mod server_talk;
+mod settings;
 mod tui;
 
 use components::app::{
@@ -14,14 +15,71 @@ use crossterm::{
     execute,
     terminal::{EnterAlternateScreen, LeaveAlternateScreen}
 };
+use dotenv::dotenv;
+use settings::{config::Config, profiles::Profiles};
 use std::{
+    env,
     io,
     io::Result,
+    path::PathBuf,
+    process,
 };
-use server_talk::session::Session;
+use tokio::runtime::Runtime;
 
 
+const USAGE: &str = "Usage: TermoChatClient [--profile NAME] [--config FILE]";
+
+// Options given on the command line
+#[derive(Default)]
+struct Args {
+    profile: Option<String>,
+    config:  Option<PathBuf>,
+}
+
+impl Args {
+    fn parse(mut args: impl Iterator<Item = String>) -> std::result::Result<Self, String> {
+        let mut parsed = Self::default();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--profile" => {
+                    parsed.profile = Some(args.next().ok_or("--profile needs a name")?);
+                },
+                "--config" => {
+                    parsed.config = Some(args.next().ok_or("--config needs a file")?.into());
+                },
+                "-h" | "--help" => return Err(USAGE.to_string()),
+                _ => match arg.split_once('=') {
+                    Some(("--profile", name)) => parsed.profile = Some(name.to_string()),
+                    Some(("--config", file))  => parsed.config = Some(file.into()),
+                    _                         => return Err(format!("Unknown argument {arg}\n{USAGE}")),
+                },
+            }
+        }
+        Ok(parsed)
+    }
+}
+
+// Nothing is drawn yet, so problems go straight to the terminal
+fn fail(msg: impl std::fmt::Display) -> ! {
+    eprintln!("{msg}");
+    process::exit(2)
+}
+
 fn main() -> Result<()>{
+    dotenv().ok();
+    let args     = Args::parse(env::args().skip(1)).unwrap_or_else(|e| fail(e));
+    let config   = Config::load(args.config.as_deref()).unwrap_or_else(|e| fail(e));
+    let profiles = Profiles::load(&Profiles::default_path(), args.profile.as_deref(), config.server.host.clone())
+        .unwrap_or_else(|e| fail(e));
+
+    // Bad preferences would only show up when switching, catch them now
+    for p in &profiles.list {
+        config.for_profile(&p.name, &p.preferences).unwrap_or_else(|e| fail(e));
+    }
+
+    // Session calls and the chat socket live here, UI stays on main thread
+    let runtime = Runtime::new()?;
+
     ratatui::init();
     let mut stderr = io::stderr();
     execute!(stderr, EnterAlternateScreen)?;
@@ -29,7 +87,7 @@ fn main() -> Result<()>{
     // core
     let backend = CrosstermBackend::new(stderr);
     let mut terminal = Terminal::new(backend)?;
-    let mut app = App::new();
+    let mut app = App::new(runtime.handle().clone(), profiles, config);
     let _res = run_app(&mut terminal, &mut app);
 
     execute!(terminal.backend_mut(), LeaveAlternateScreen)?;
@@ -0,0 +1,159 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+
+// Gives up on a room after this many failed attempts in a row
+pub const MAX_RETRIES: u32 = 8;
+const BACKOFF_BASE:    Duration = Duration::from_millis(500);
+const BACKOFF_CAP:     Duration = Duration::from_secs(30);
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ConnState {
+    Connecting,
+    Connected,
+    Reconnecting(u32), // Attempt number
+    Offline,
+}
+
+impl fmt::Display for ConnState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConnState::Connecting      => write!(f, "Connecting"),
+            ConnState::Connected       => write!(f, "Connected"),
+            ConnState::Reconnecting(n) => write!(f, "Reconnecting ({n}/{MAX_RETRIES})"),
+            ConnState::Offline         => write!(f, "Offline"),
+        }
+    }
+}
+
+// Everything the chat task hands over to the UI
+#[derive(Debug)]
+pub enum WsEvent {
+    Message(ChatMessage),
+    State(ConnState),
+    Problem(String), // Failure the task recovers from on its own
+}
+
+// Why a single connection ended
+pub enum ChatExit {
+    Stopped, // Left on purpose, don't come back
+    Dropped, // Server or network went away
+}
+
+// Exponential delay with up to 50% random jitter so clients don't reconnect in lockstep
+pub fn backoff(attempt: u32) -> Duration {
+    let exp   = BACKOFF_BASE.saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)));
+    let delay = exp.min(BACKOFF_CAP);
+    let jitter = delay.mul_f64(fastrand::f64() * 0.5);
+    delay + jitter
+}
+
+// Bumped whenever ChatMessage changes shape on the wire
+pub const ENVELOPE_VERSION: u32 = 1;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MessageKind {
+    Text,
+    System,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ChatMessage {
+    pub v:         u32,
+    pub id:        String,
+    pub room:      String,
+    pub sender:    String,
+    pub timestamp: u64,    // Unix millis
+    pub kind:      MessageKind,
+    pub body:      String, // Lines are separated by real newlines
+}
+
+impl ChatMessage {
+    pub fn new(room: &str, sender: &str, body: String) -> Self {
+        Self {
+            v:         ENVELOPE_VERSION,
+            id:        format!("{:016x}", fastrand::u64(..)),
+            room:      room.to_string(),
+            sender:    sender.to_string(),
+            timestamp: now_millis(),
+            kind:      MessageKind::Text,
+            body,
+        }
+    }
+
+    // Anything that isn't an envelope is treated as old style text
+    pub fn from_frame(frame: &str, room: &str) -> Self {
+        match serde_json::from_str::<ChatMessage>(frame) {
+            Ok(msg) => msg,
+            Err(_)  => Self::legacy(frame, room),
+        }
+    }
+
+    // Old clients send lines glued together, each but the last ending in a backslash
+    fn legacy(frame: &str, room: &str) -> Self {
+        let body = frame.split('\\')
+            .filter(|s| !s.is_empty())
+            .collect::<Vec<&str>>()
+            .join("\n");
+        Self::new(room, "", body)
+    }
+
+    pub fn to_frame(&self) -> String {
+        // Plain data, serializing it can't fail
+        serde_json::to_string(self).unwrap_or_default()
+    }
+
+    pub fn lines(&self) -> impl Iterator<Item = &str> {
+        self.body.lines()
+    }
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backoff_doubles_up_to_the_cap_with_bounded_jitter() {
+        for _ in 0..100 {
+            for (attempt, base) in [(1, 500), (2, 1_000), (4, 4_000), (7, 30_000), (50, 30_000)] {
+                let base  = Duration::from_millis(base);
+                let delay = backoff(attempt);
+                assert!(delay >= base && delay <= base.mul_f64(1.5), "attempt {attempt} waited {delay:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn envelopes_round_trip() {
+        let msg = ChatMessage::new("room", "ali", "first\nsecond".to_string());
+        let back = ChatMessage::from_frame(&msg.to_frame(), "elsewhere");
+        assert_eq!(back, msg);
+        assert_eq!(back.lines().collect::<Vec<_>>(), ["first", "second"]);
+    }
+
+    #[test]
+    fn old_frames_fall_back_to_legacy_text() {
+        let msg = ChatMessage::from_frame("a\\b", "room");
+        assert_eq!(msg.body, "a\nb");
+        assert_eq!(msg.sender, "");
+        assert_eq!(msg.room, "room");
+        assert_eq!(msg.kind, MessageKind::Text);
+
+        // JSON that isn't an envelope is still just text
+        let msg = ChatMessage::from_frame(r#"{"text":"hi"}"#, "room");
+        assert_eq!(msg.body, r#"{"text":"hi"}"#);
+        assert_eq!(msg.sender, "");
+    }
+}
@@ -0,0 +1,75 @@
+use reqwest::StatusCode;
+use std::fmt;
+
+
+#[derive(Debug)]
+pub enum SessionError {
+    Transport(reqwest::Error),                         // Server unreachable, timeouts, ...
+    Status { code: StatusCode, body: String },         // Non 2xx without a usable body
+    Decode(String),                                    // Body isn't what we expected
+    Server(String),                                    // Server answered with status "error"
+    MissingToken,                                      // Call needs a login first
+    Socket(Box<tungstenite::Error>),                   // WebSocket side failures
+    Store(String),                                     // Saved credentials can't be read or written
+}
+
+impl fmt::Display for SessionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SessionError::Transport(e)         => write!(f, "Couldn't reach server: {e}"),
+            SessionError::Status { code, body } => {
+                if body.is_empty() {
+                    write!(f, "Server responded with {code}")
+                } else {
+                    write!(f, "Server responded with {code}: {body}")
+                }
+            },
+            SessionError::Decode(what)         => write!(f, "Unexpected response: {what}"),
+            SessionError::Server(msg)          => write!(f, "{msg}"),
+            SessionError::MissingToken         => write!(f, "You need to log in first"),
+            SessionError::Socket(e)            => write!(f, "Chat connection failed: {e}"),
+            SessionError::Store(msg)           => write!(f, "Credential store: {msg}"),
+        }
+    }
+}
+
+impl SessionError {
+    // Retrying won't help: the request can't be built or the server turned the handshake down
+    pub fn is_fatal(&self) -> bool {
+        match self {
+            SessionError::MissingToken | SessionError::Decode(_) => true,
+            SessionError::Socket(e)                              => match e.as_ref() {
+                tungstenite::Error::Http(response)                             => response.status().is_client_error(),
+                tungstenite::Error::Url(_) | tungstenite::Error::HttpFormat(_) => true,
+                _                                                              => false,
+            },
+            _                                                    => false,
+        }
+    }
+}
+
+impl std::error::Error for SessionError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SessionError::Transport(e) => Some(e),
+            SessionError::Socket(e)    => Some(e.as_ref()),
+            _                          => None,
+        }
+    }
+}
+
+impl From<reqwest::Error> for SessionError {
+    fn from(e: reqwest::Error) -> Self {
+        if e.is_decode() {
+            SessionError::Decode(e.to_string())
+        } else {
+            SessionError::Transport(e)
+        }
+    }
+}
+
+impl From<tungstenite::Error> for SessionError {
+    fn from(e: tungstenite::Error) -> Self {
+        SessionError::Socket(Box::new(e))
+    }
+}
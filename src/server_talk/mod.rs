@@ -1 +1,4 @@
+pub mod chat;
+pub mod errors;
 pub mod session;
+pub mod store;
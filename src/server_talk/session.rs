@@ -1,57 +1,139 @@
-use crossbeam_channel::{Receiver, Sender};
-use dotenv::dotenv;
+use crate::settings::profiles::Profile;
+use crossbeam_channel::Sender;
+use futures_util::{SinkExt, StreamExt};
 use http::header::HeaderValue;
+use native_tls::TlsConnector;
 use reqwest::{
-    blocking::{Client, RequestBuilder, Response},
     header::{AUTHORIZATION, CONTENT_TYPE},
+    Client, RequestBuilder, Response,
 };
-use serde_json::{json, Map, Value};
+use serde_json::{json, Value};
 use std::{
-    collections::HashMap,
+    collections::VecDeque,
     env,
-    fs::{File, OpenOptions},
-    io::{BufRead, BufReader, Write},
+    sync::Arc,
 };
-use tungstenite::{client::IntoClientRequest, connect, stream::NoDelay, Message};
+use tokio::sync::{
+    mpsc::UnboundedReceiver,
+    oneshot,
+};
+use tokio_tungstenite::{connect_async_tls_with_config, Connector};
+use tungstenite::{client::IntoClientRequest, Message};
+use super::{
+    chat::{backoff, ChatExit, ChatMessage, ConnState, WsEvent, MAX_RETRIES},
+    errors::SessionError,
+    store::{CredentialStore, Credentials, FileStore},
+};
+
+
+pub struct LoginResponse {
+    pub token:     String,
+    pub show_name: String,
+}
+
+// Who can find a room, goes to the server as its is_public flag
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Visibility {
+    Public,
+    Private,
+}
+
+impl Visibility {
+    pub const ALL: [Visibility; 2] = [Visibility::Public, Visibility::Private];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Visibility::Public  => "Public",
+            Visibility::Private => "Private",
+        }
+    }
+
+    pub fn from_label(label: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|v| v.label() == label)
+    }
+
+    pub fn from_public(is_public: bool) -> Self {
+        match is_public {
+            true  => Visibility::Public,
+            false => Visibility::Private,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct RoomList {
+    pub names:      Vec<String>,
+    pub hashes:     Vec<String>,
+    pub visibility: Vec<Visibility>, // One per room, lined up with names
+    pub problem:    Option<String>,  // Why only part of the list could be fetched
+}
 
+impl RoomList {
+    // Rooms of other that aren't listed yet go after these, so each room shows once
+    pub fn merge(mut self, other: RoomList) -> Self {
+        let rooms = other.names.into_iter().zip(other.hashes).zip(other.visibility);
+        for ((name, hash), visibility) in rooms {
+            match self.hashes.iter().position(|h| *h == hash) {
+                // Either list calling it public is enough
+                Some(i) if visibility == Visibility::Public => self.visibility[i] = visibility,
+                Some(_)                                     => {},
+                None                                        => {
+                    self.names.push(name);
+                    self.hashes.push(hash);
+                    self.visibility.push(visibility);
+                },
+            }
+        }
+        self
+    }
+}
 
 #[derive(Clone)]
 pub struct Session {
     pub host:          String,
     pub client:        Client,
     pub token:         Option<String>,
+    pub show_name:     Option<String>,       // Known once logged in
     pub content_type:  String,
-    pub from_cli:      Receiver<String>,
-    pub to_cli:        Sender<String>,
-    pub stop_from_cli: Receiver<bool>,
+    pub tls:           Option<TlsConnector>, // None trusts the system roots only
+    pub store:         Arc<dyn CredentialStore>,
+    pub to_cli:        Sender<WsEvent>,
 }
 
 impl Session {
-    pub fn new(fr_c: Receiver<String>, to_c: Sender<String>, stop: Receiver<bool>) -> Self {
-        dotenv().ok();
-        let token_: Option<String> = match env::var("TOKEN") {
-            Ok(value) => Some(value),
-            _ => None,
+    pub fn new(
+        profile: &Profile,
+        to_c: Sender<WsEvent>,
+    ) -> (Self, Option<SessionError>) {
+        let store = FileStore::new(FileStore::profile_path(&profile.name), env::var("TOKEN_PASSPHRASE").ok());
+        // Unreadable credentials mean logging in again, the caller gets to say why
+        let (creds, load_err) = match store.load() {
+            Ok(creds) => (creds, None),
+            Err(e)    => (None, Some(e)),
         };
+        let token_: Option<String> = creds.as_ref().map(|c| c.token.clone());
+        let name_:  Option<String> = creds.map(|c| c.show_name);
 
-        Self {
-            host: env::var("HOST").expect("Host doesn't exists!"),
+        let session = Self {
+            host:         profile.host.clone(),
             client:       Client::new(),
             token:        token_,
+            show_name:    name_,
             content_type: "application/json".to_string(),
-            from_cli:     fr_c,
+            tls:          None,
+            store:        Arc::new(store),
             to_cli:       to_c,
-            stop_from_cli:stop,
-        }
+        };
+        (session, load_err)
     }
 
-    fn request(
+    async fn request(
         &self,
         url: String,
         typ: &str,
         token: Option<String>,
         form: Option<&Value>,
-    ) -> Value {
+    ) -> Result<Value, SessionError> {
         let mut response: RequestBuilder;
         match typ {
             "get" => {
@@ -90,90 +172,88 @@ impl Session {
             None => response,
         };
 
-        let response: Response = response.send().expect("Request isn't sent properly!");
-        response.json().unwrap()
-    }
-
-    // This alters an ENV variable inside .env file and resets
-    // runtime ENVs
-    fn set_dotenv_var(&mut self, key: &str, value: String) {
-        let file = File::open(".env").expect("Failed to open .env file");
-        let reader = BufReader::new(file);
-        let mut found = false;
-
-        let lines: Vec<String> = reader
-            .lines()
-            .filter_map(Result::ok)
-            .map(|line| {
-                if line.trim_start().starts_with(&format!("{key}=")) {
-                    found = true;
-                    format!("{key}={value}")
-                } else {
-                    line
-                }
-            })
-            .collect();
+        let response: Response = response.send().await?;
+        let code = response.status();
+        let body = response.text().await?;
 
-        let final_lines = if !found {
-            let mut new_lines = lines.clone();
-            new_lines.push(format!("{key}={value}"));
-            new_lines
-        } else {
-            lines
-        };
-
-        let mut file = OpenOptions::new()
-            .write(true)
-            .truncate(true)
-            .open(".env")
-            .expect("Failed to open .env file for writing");
+        // Server reports most failures inside JSON, so try that before the status code
+        match serde_json::from_str::<Value>(&body) {
+            Ok(value) if value.is_object() => Ok(value),
+            _ if !code.is_success()        => Err(SessionError::Status { code, body }),
+            Ok(_)                          => Err(SessionError::Decode("body isn't a JSON object".to_string())),
+            Err(e)                         => Err(SessionError::Decode(e.to_string())),
+        }
+    }
 
-        for line in final_lines {
-            writeln!(file, "{}", line).expect("Failed to write to .env file");
+    // http(s):// host becomes ws(s):// so TLS deployments end up on wss
+    fn ws_url(&self, path: &str) -> String {
+        if let Some(rest) = self.host.strip_prefix("https://") {
+            format!("wss://{rest}{path}")
+        } else if let Some(rest) = self.host.strip_prefix("http://") {
+            format!("ws://{rest}{path}")
+        } else {
+            format!("{}{path}", self.host)
         }
-        dotenv().ok();
+    }
+
+    fn token(&self) -> Result<String, SessionError> {
+        self.token.clone().ok_or(SessionError::MissingToken)
     }
 
     // Let this function take care of all response types
-    fn resp_val(&self, data: &Value, key: &str) -> Value {
-        let map: Map<String, Value> = data.as_object().unwrap().clone();
+    fn resp_val(&self, data: &Value, key: &str) -> Result<Value, SessionError> {
+        data.as_object()
+            .and_then(|map| map.get(key))
+            .cloned()
+            .ok_or_else(|| SessionError::Decode(format!("missing \"{key}\"")))
+    }
 
-        let value: Value = map.get(key).expect("No such key!").clone();
-        value
+    fn resp_str(&self, data: &Value, key: &str) -> Result<String, SessionError> {
+        let value = self.resp_val(data, key)?;
+        value.as_str()
+            .map(|s| s.to_string())
+            .ok_or_else(|| SessionError::Decode(format!("\"{key}\" isn't a string")))
     }
 
-    fn resp_str(&self, data: &Value, key: &str) -> String {
-        let value = self.resp_val(data, key);
-        let value = value.as_str().map(|s| s.to_string()).unwrap();
-        value
+    fn resp_flags(&self, data: &Value, key: &str) -> Result<Vec<bool>, SessionError> {
+        let value = self.resp_val(data, key)?;
+        Ok(value.as_array()
+            .unwrap_or(&vec![])
+            .iter()
+            .map(|v| v.as_bool().unwrap_or(false))
+            .collect())
     }
 
-    fn resp_arr(&self, data: &Value, key: &str) -> Vec<String> {
-        let value = self.resp_val(data, key);
-        value.as_array()
+    fn resp_arr(&self, data: &Value, key: &str) -> Result<Vec<String>, SessionError> {
+        let value = self.resp_val(data, key)?;
+        Ok(value.as_array()
             .unwrap_or(&vec![])
             .iter()
             .filter_map(|v| v.as_str())
             .map(|v| v.to_string())
-            .collect()
+            .collect())
     }
 
-    fn check_stat(&self, response: &Value) -> bool {
-        let status = self.resp_str(response, "status");
+    // Turns a status "error" body into Err and hands back "message" otherwise
+    fn check_stat(&self, response: &Value) -> Result<String, SessionError> {
+        let status = self.resp_str(response, "status")?;
 
-        match status.as_str() {
-            "ok" => true,
-            _    => false,
+        if status == "ok" {
+            Ok(self.resp_str(response, "message").unwrap_or_default())
+        } else {
+            let err = self.resp_str(response, "error")
+                .unwrap_or_else(|_| "Unknown server error".to_string());
+            Err(SessionError::Server(err))
         }
     }
 
-    pub fn signup(
+    pub async fn signup(
         &self,
         show_name: &str,
         password: &str,
         related_question: &str,
         related_answer: &str,
-    ) -> HashMap<&str, String> {
+    ) -> Result<String, SessionError> {
         let url = format!("{}/auth/signup", self.host);
         let form = json!({
             "show_name":         show_name.to_string(),
@@ -182,291 +262,678 @@ impl Session {
             "related_answer":    related_answer.to_string(),
         });
 
-        let response = self.request(url, "post", None, Some(&form));
-
-        let mut map = HashMap::new();
-        if !self.check_stat(&response) {
-            let err = self.resp_str(&response, "error");
-            map.insert("error", err.to_string());
-            map
-        } else {
-            let msg = self.resp_str(&response, "message");
-            map.insert("ok", msg.to_string());
-            map
-        }
+        let response = self.request(url, "post", None, Some(&form)).await?;
+        self.check_stat(&response)
     }
 
-    pub fn login(&mut self, show_name: &str, password: &str) -> HashMap<&str, String> {
+    // Caller is in charge of putting the returned token into its Session.
+    // Unless remembered, the token lives only as long as the Session and older saved ones are dropped
+    pub async fn login(&self, show_name: &str, password: &str, remember: bool) -> Result<LoginResponse, SessionError> {
         let url = format!("{}/auth/login", self.host);
         let form = json!({
             "show_name":         show_name.to_string(),
             "password":          password.to_string(),
         });
 
-        let response = self.request(url, "post", None, Some(&form));
+        let response = self.request(url, "post", None, Some(&form)).await?;
+        self.check_stat(&response)?;
+        let data     = self.resp_val(&response, "data")?;
+        let token    = self.resp_str(&data, "token")?;
 
-        let mut map = HashMap::new();
-        if !self.check_stat(&response) {
-            let err = self.resp_str(&response, "error");
-            map.insert("error", err.to_string());
-            map
-        } else {
-            let msg   = self.resp_str(&response, "message");
-            let data  = self.resp_val(&response, "data");
-            let token = self.resp_str(&data, "token");
-
-            // Set token into .env file
-            self.set_dotenv_var("TOKEN", token.clone());
-            self.token = Some(token.clone());
-            map.insert("ok", msg.to_string());
-            map.insert("token", token.clone().to_string());
-            map
+        match remember {
+            true  => self.store.save(&Credentials { token: token.clone(), show_name: show_name.to_string() })?,
+            false => self.store.clear()?,
         }
+        Ok(LoginResponse { token, show_name: show_name.to_string() })
     }
 
-    pub fn user_exists(&self, show_name: &str) -> HashMap<&str, String> {
+    pub async fn user_exists(&self, show_name: &str) -> Result<String, SessionError> {
         let url = format!("{}/auth/uexist", self.host);
         let form = json!({
             "show_name": show_name.to_string(),
         });
 
-        let response = self.request(url, "post", None, Some(&form));
+        let response = self.request(url, "post", None, Some(&form)).await?;
+        self.check_stat(&response)
+    }
 
-        let mut map = HashMap::new();
-        if !self.check_stat(&response) {
-            let err = self.resp_str(&response, "error");
-            map.insert("error", err.to_string());
-            map
-        } else {
-            let msg = self.resp_str(&response, "message");
-            map.insert("ok", msg.to_string());
-            map
-        }
+    // Security question given on sign up, unknown names fail on user_exists first
+    pub async fn recovery_question(&self, show_name: &str) -> Result<String, SessionError> {
+        self.user_exists(show_name).await?;
+
+        let url = format!("{}/auth/question", self.host);
+        let form = json!({
+            "show_name": show_name.to_string(),
+        });
+
+        let response = self.request(url, "post", None, Some(&form)).await?;
+        self.check_stat(&response)?;
+        let data = self.resp_val(&response, "data")?;
+        self.resp_str(&data, "related_question")
     }
 
-    pub fn logout(&self) -> HashMap<&str, String> {
-        let url = format!("{}/users/logout", self.host);
-        let token = self.token.clone().unwrap();
+    pub async fn recover(&self, show_name: &str, related_answer: &str, new_pass: &str) -> Result<String, SessionError> {
+        let url = format!("{}/auth/recover", self.host);
+        let form = json!({
+            "show_name":         show_name.to_string(),
+            "related_answer":    related_answer.to_string(),
+            "new_pass":          new_pass.to_string(),
+        });
 
-        let response = self.request(url, "get", Some(token), None);
+        let response = self.request(url, "post", None, Some(&form)).await?;
+        self.check_stat(&response)
+    }
 
-        let mut map = HashMap::new();
-        if !self.check_stat(&response) {
-            let err = self.resp_str(&response, "error");
-            map.insert("error", err.to_string());
-            map
-        } else {
-            let msg = self.resp_str(&response, "message");
-            map.insert("ok", msg.to_string());
-            map
-        }
+    // Stored token is dropped first, we're logged out here even if the server can't be told.
+    // Only a Store error means we're still logged in.
+    pub async fn logout(&self) -> Result<String, SessionError> {
+        let url = format!("{}/users/logout", self.host);
+        self.store.clear()?;
+        let token = self.token()?;
+
+        let response = self.request(url, "get", Some(token), None).await?;
+        self.check_stat(&response)
     }
 
     // In case for checking token is still valid
-    pub fn ping(&self) -> HashMap<&str, String> {
+    pub async fn ping(&self) -> Result<String, SessionError> {
         let url = format!("{}/users/ping", self.host);
-        let token = self.token.clone().unwrap();
+        let token = self.token()?;
 
-        let response = self.request(url, "get", Some(token), None);
-
-        let mut map = HashMap::new();
-        if !self.check_stat(&response) {
-            let err = self.resp_str(&response, "error");
-            map.insert("error", err.to_string());
-            map
-        } else {
-            let msg = self.resp_str(&response, "message");
-            map.insert("ok", msg.to_string());
-            map
-        }
+        let response = self.request(url, "get", Some(token), None).await?;
+        self.check_stat(&response)
     }
 
-    pub fn user_rename(&self, show_name: &str) -> HashMap<&str, String> {
+    // Stored credentials follow the new name, caller updates its own Session
+    pub async fn user_rename(&self, show_name: &str) -> Result<String, SessionError> {
         let url = format!("{}/users/rename", self.host);
-        let token = self.token.clone().unwrap();
+        let token = self.token()?;
         let form = json!({
             "show_name":         show_name.to_string(),
         });
 
-        let response = self.request(url, "put", Some(token), Some(&form));
-
-        let mut map = HashMap::new();
-        if !self.check_stat(&response) {
-            let err = self.resp_str(&response, "error");
-            map.insert("error", err.to_string());
-            map
-        } else {
-            let msg = self.resp_str(&response, "message");
-            map.insert("ok", msg.to_string());
-            map
-        }
+        let response = self.request(url, "put", Some(token.clone()), Some(&form)).await?;
+        self.check_stat(&response)?;
+        self.store.save(&Credentials { token, show_name: show_name.to_string() })?;
+        Ok(show_name.to_string())
     }
 
-    pub fn user_repass(&self, current_pass: &str, new_pass: &str) -> HashMap<&str, String> {
+    pub async fn user_repass(&self, current_pass: &str, new_pass: &str) -> Result<String, SessionError> {
         let url = format!("{}/users/repass", self.host);
-        let token = self.token.clone().unwrap();
+        let token = self.token()?;
         let form = json!({
             "current_pass":         current_pass.to_string(),
             "new_pass":             new_pass.to_string(),
         });
 
-        let response = self.request(url, "put", Some(token), Some(&form));
-
-        let mut map = HashMap::new();
-        if !self.check_stat(&response) {
-            let err = self.resp_str(&response, "error");
-            map.insert("error", err.to_string());
-            map
-        } else {
-            let msg = self.resp_str(&response, "message");
-            map.insert("ok", msg.to_string());
-            map
-        }
+        let response = self.request(url, "put", Some(token), Some(&form)).await?;
+        self.check_stat(&response)
     }
 
-    pub fn room_build(&self, name: &str, is_public: &str) -> HashMap<&str, String> {
+    pub async fn room_build(&self, name: &str, visibility: Visibility) -> Result<String, SessionError> {
         let url = format!("{}/rooms/build", self.host);
-        let token = self.token.clone().unwrap();
+        let token = self.token()?;
         let form = json!({
             "name":                  name.to_string(),
-            "is_public":             is_public.to_string(),
+            "is_public":             visibility == Visibility::Public,
         });
 
-        let response = self.request(url, "post", Some(token), Some(&form));
-
-        let mut map = HashMap::new();
-        if !self.check_stat(&response) {
-            let err = self.resp_str(&response, "error");
-            map.insert("error", err.to_string());
-            map
-        } else {
-            let msg = self.resp_str(&response, "message");
-            map.insert("ok", msg.to_string());
-            map
-        }
+        let response = self.request(url, "post", Some(token), Some(&form)).await?;
+        self.check_stat(&response)
     }
 
-    pub fn room_close(&self, hash: &str) -> HashMap<&str, String> {
+    pub async fn room_close(&self, hash: &str) -> Result<String, SessionError> {
         let url = format!("{}/rooms/close", self.host);
-        let token = self.token.clone().unwrap();
+        let token = self.token()?;
         let form = json!({
             "hash":                  hash.to_string(),
         });
 
-        let response = self.request(url, "del", Some(token), Some(&form));
-
-        let mut map = HashMap::new();
-        if !self.check_stat(&response) {
-            let err = self.resp_str(&response, "error");
-            map.insert("error", err.to_string());
-            map
-        } else {
-            let msg = self.resp_str(&response, "message");
-            map.insert("ok", msg.to_string());
-            map
-        }
+        let response = self.request(url, "del", Some(token), Some(&form)).await?;
+        self.check_stat(&response)
     }
 
-    pub fn room_rename(&self, hash: &str, new_name: &str) -> HashMap<&str, String> {
+    pub async fn room_rename(&self, hash: &str, new_name: &str) -> Result<String, SessionError> {
         let url = format!("{}/rooms/rename", self.host);
-        let token = self.token.clone().unwrap();
+        let token = self.token()?;
         let form = json!({
             "hash":                  hash.to_string(),
             "new_name":              new_name.to_string(),
         });
 
-        let response = self.request(url, "put", Some(token), Some(&form));
+        let response = self.request(url, "put", Some(token), Some(&form)).await?;
+        self.check_stat(&response)
+    }
 
-        let mut map = HashMap::new();
-        if !self.check_stat(&response) {
-            let err = self.resp_str(&response, "error");
-            map.insert("error", err.to_string());
-            map
-        } else {
-            let msg = self.resp_str(&response, "message");
-            map.insert("ok", msg.to_string());
-            map
+    pub async fn room_publist(&self) -> Result<RoomList, SessionError> {
+        let url = format!("{}/rooms/publist", self.host);
+        let token = self.token()?;
+
+        let response = self.request(url, "get", Some(token), None).await?;
+        self.check_stat(&response)?;
+
+        let data   = self.resp_val(&response, "data")?;
+        let names  = self.resp_arr(&data, "names")?;
+        let hashes = self.resp_arr(&data, "hashes")?;
+        let visibility = vec![Visibility::Public; names.len()];
+        Ok(RoomList { names, hashes, visibility, problem: None })
+    }
+
+    // Rooms we own or have joined, private ones included
+    pub async fn room_mylist(&self) -> Result<RoomList, SessionError> {
+        let url = format!("{}/rooms/mylist", self.host);
+        let token = self.token()?;
+
+        let response = self.request(url, "get", Some(token), None).await?;
+        self.check_stat(&response)?;
+
+        let data   = self.resp_val(&response, "data")?;
+        let names  = self.resp_arr(&data, "names")?;
+        let hashes = self.resp_arr(&data, "hashes")?;
+        let mut publics = self.resp_flags(&data, "publics").unwrap_or_default();
+        publics.resize(names.len(), false); // Unflagged rooms are taken as private
+        let visibility = publics.into_iter().map(Visibility::from_public).collect();
+        Ok(RoomList { names, hashes, visibility, problem: None })
+    }
+
+    // What the room list shows: ours first, then the public ones we're not in
+    pub async fn room_list(&self) -> Result<RoomList, SessionError> {
+        let (mine, public) = tokio::join!(self.room_mylist(), self.room_publist());
+        match mine {
+            Ok(mine) => Ok(mine.merge(public?)),
+            // Servers without mylist still get the public rooms
+            Err(e)   => Ok(RoomList { problem: Some(format!("Only public rooms listed: {e}")), ..public? }),
         }
     }
 
-    pub fn room_publist(&self) -> HashMap<&str, Vec<String>> {
-        let url = format!("{}/rooms/publist", self.host);
-        let token = self.token.clone().unwrap();
+    fn conn_state(&self, state: ConnState) {
+        let _ = self.to_cli.send(WsEvent::State(state));
+    }
 
-        let response = self.request(url, "get", Some(token.clone()), None);
+    // Goes offline, telling the UI about messages that never made it out
+    fn leave(&self, from_cli: &mut UnboundedReceiver<String>, outbox: &VecDeque<String>) {
+        from_cli.close();
+        let mut unsent = outbox.len();
+        while from_cli.try_recv().is_ok() {
+            unsent += 1;
+        }
+        if unsent > 0 {
+            let _ = self.to_cli.send(WsEvent::Problem(format!("{unsent} unsent message(s) dropped")));
+        }
+        self.conn_state(ConnState::Offline);
+    }
 
-        let mut map = HashMap::new();
-        if !self.check_stat(&response) {
-            let err = self.resp_str(&response, "error");
-            map.insert("error", vec![format!("{err}")]);
-            map
-        } else {
-            let data   = self.resp_val(&response, "data");
-            let names  = self.resp_arr(&data, "names");
-            let hashes = self.resp_arr(&data, "hashes");
-            //let names = self.res(&names);
-            //let hashes = self.val2vec(&hashes);
-            map.insert("ok", vec!["".to_string()]);
-            map.insert("names", names);
-            map.insert("hashes", hashes);
-            map
+    // Keeps the room alive, reconnecting with backoff until stop fires or is dropped, or we give up.
+    // from_cli is the room's own outgoing queue, whatever is still in it when we leave is reported
+    pub async fn chat_connect(
+        &self,
+        room_hash: &str,
+        mut from_cli: UnboundedReceiver<String>,
+        mut stop: oneshot::Receiver<()>,
+    ) -> Result<(), SessionError> {
+        let mut outbox  = VecDeque::new(); // Typed while offline
+        let mut attempt = 0;
+
+        self.conn_state(ConnState::Connecting);
+        loop {
+            let last_err = match self.chat_once(room_hash, &mut from_cli, &mut stop, &mut outbox, &mut attempt).await {
+                Ok(ChatExit::Stopped)  => {
+                    self.leave(&mut from_cli, &outbox);
+                    return Ok(())
+                },
+                Ok(ChatExit::Dropped)  => None,
+                // Retrying can't fix a bad request or a refused login
+                Err(e) if e.is_fatal() => {
+                    self.leave(&mut from_cli, &outbox);
+                    return Err(e)
+                },
+                Err(e)                 => Some(e),
+            };
+
+            attempt += 1;
+            if attempt > MAX_RETRIES {
+                self.leave(&mut from_cli, &outbox);
+                return last_err.map_or(Ok(()), Err);
+            }
+            if let Some(e) = &last_err {
+                let _ = self.to_cli.send(WsEvent::Problem(format!("Chat connection lost: {e}")));
+            }
+            self.conn_state(ConnState::Reconnecting(attempt));
+
+            // Sit the delay out but keep listening to the UI meanwhile
+            let wait = tokio::time::sleep(backoff(attempt));
+            tokio::pin!(wait);
+            loop {
+                tokio::select! {
+                    _ = &mut wait => break,
+
+                    _ = &mut stop => {
+                        self.leave(&mut from_cli, &outbox);
+                        return Ok(())
+                    },
+
+                    msg = from_cli.recv() => match msg {
+                        Some(msg) => outbox.push_back(msg),
+                        None      => {
+                            // Room was left without a stop
+                            self.leave(&mut from_cli, &outbox);
+                            return Ok(())
+                        },
+                    },
+                }
+            }
         }
     }
 
-    pub fn chat_connect(&self, room_hash: &str) -> Result<(), Box<dyn std::error::Error>> {
-        let url = format!("{}/chat/manage", self.host).replace("http", "ws");
+    // A single socket lifetime, unsent messages are left in outbox
+    async fn chat_once(
+        &self,
+        room_hash: &str,
+        from_cli: &mut UnboundedReceiver<String>,
+        stop: &mut oneshot::Receiver<()>,
+        outbox: &mut VecDeque<String>,
+        attempt: &mut u32,
+    ) -> Result<ChatExit, SessionError> {
+        let url = self.ws_url("/chat/manage");
         let mut request = url.into_client_request()?;
-        let token = format!("Bearer {}", self.token.clone().unwrap());
+        let token = format!("Bearer {}", self.token()?);
+        let token = HeaderValue::from_str(&token)
+            .map_err(|e| SessionError::Decode(e.to_string()))?;
+        let room_header = HeaderValue::from_str(room_hash)
+            .map_err(|e| SessionError::Decode(e.to_string()))?;
 
         // headers
         request
             .headers_mut()
-            .insert("Authorization", HeaderValue::from_str(&token).unwrap());
+            .insert("Authorization", token);
         request
             .headers_mut()
-            .insert("X-Room-Hash", HeaderValue::from_str(room_hash).unwrap());
+            .insert("X-Room-Hash", room_header);
+
+        let connector = self.tls.clone().map(Connector::NativeTls);
+        let (socket, _) = connect_async_tls_with_config(request, None, false, connector).await?;
+        let (mut write, mut read) = socket.split();
+        *attempt = 0;
+        self.conn_state(ConnState::Connected);
+
+        while let Some(msg) = outbox.pop_front() {
+            if let Err(e) = write.send(Message::Text(msg.clone().into())).await {
+                outbox.push_front(msg);
+                return Err(e.into());
+            }
+        }
 
-        let (mut socket, _) = connect(request)?;
+        loop {
+            tokio::select! {
+                _ = &mut *stop => {
+                    let _ = write.send(Message::Close(None)).await;
+                    return Ok(ChatExit::Stopped)
+                },
+
+                msg = from_cli.recv() => match msg {
+                    Some(msg) => {
+                        if let Err(e) = write.send(Message::Text(msg.clone().into())).await {
+                            outbox.push_back(msg);
+                            return Err(e.into());
+                        }
+                    },
+                    None      => return Ok(ChatExit::Stopped), // Room was left
+                },
+
+                frame = read.next() => match frame {
+                    Some(Ok(Message::Text(text))) => {
+                        // UI is gone, nothing left to deliver to
+                        let msg = ChatMessage::from_frame(&text, room_hash);
+                        if self.to_cli.send(WsEvent::Message(msg)).is_err() {
+                            return Ok(ChatExit::Stopped)
+                        }
+                    },
+                    Some(Ok(Message::Close(_))) | None => return Ok(ChatExit::Dropped),
+                    Some(Ok(_))                        => {},
+                    Some(Err(e))                       => return Err(e.into()),
+                },
+            }
+        }
+    }
+}
 
-        // This block makes stream happen imidiatly and not adding up to a buffer
-        match socket.get_mut() {
-            tungstenite::stream::MaybeTlsStream::Plain(stream) => stream.set_nonblocking(true),
-            _ => unimplemented!(),
-        }?;
-        ///////////////////////////////////////////////////////////////////////
 
-        loop {
-            if let Ok(flag) = self.stop_from_cli.try_recv() {
-                if flag {
-                    socket.close(None)?;
-                    break
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::server_talk::store::tests::scratch_store;
+    use crossbeam_channel::unbounded;
+    use native_tls::{Certificate, Identity};
+    use openssl::{
+        asn1::Asn1Time,
+        hash::MessageDigest,
+        pkey::PKey,
+        rsa::Rsa,
+        x509::{extension::SubjectAlternativeName, X509NameBuilder, X509},
+    };
+    use std::time::Duration;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+        sync::mpsc::unbounded_channel,
+    };
+
+    // Self-signed certificate for localhost as (cert pem, pkcs8 key pem)
+    fn self_signed() -> (Vec<u8>, Vec<u8>) {
+        let key = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+
+        let mut name = X509NameBuilder::new().unwrap();
+        name.append_entry_by_text("CN", "localhost").unwrap();
+        let name = name.build();
+
+        let mut cert = X509::builder().unwrap();
+        cert.set_version(2).unwrap();
+        cert.set_subject_name(&name).unwrap();
+        cert.set_issuer_name(&name).unwrap();
+        cert.set_pubkey(&key).unwrap();
+        cert.set_not_before(&Asn1Time::days_from_now(0).unwrap()).unwrap();
+        cert.set_not_after(&Asn1Time::days_from_now(1).unwrap()).unwrap();
+        let san = SubjectAlternativeName::new()
+            .dns("localhost")
+            .build(&cert.x509v3_context(None, None))
+            .unwrap();
+        cert.append_extension(san).unwrap();
+        cert.sign(&key, MessageDigest::sha256()).unwrap();
+
+        (cert.build().to_pem().unwrap(), key.private_key_to_pem_pkcs8().unwrap())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn chat_connect_over_wss() {
+        let (cert, key) = self_signed();
+        let identity = Identity::from_pkcs8(&cert, &key).unwrap();
+        let acceptor = tokio_native_tls::TlsAcceptor::from(
+            native_tls::TlsAcceptor::new(identity).unwrap()
+        );
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        // Echo server that answers the first text frame
+        tokio::spawn(async move {
+            let (tcp, _) = listener.accept().await.unwrap();
+            let tls = acceptor.accept(tcp).await.unwrap();
+            let mut ws = tokio_tungstenite::accept_async(tls).await.unwrap();
+            while let Some(Ok(msg)) = ws.next().await {
+                if let Message::Text(text) = msg {
+                    ws.send(Message::Text(format!("echo: {text}").into())).await.unwrap();
                 }
             }
+        });
 
-            if let Ok(msg) = self.from_cli.try_recv() {
-                if let Err(e) = socket.write(Message::Text(msg.into())) {
-                    eprintln!("Write error: {}", e);
-                    break
+        let (tx_to_ws, rx_from_cli)      = unbounded_channel::<String>();
+        let (tx_to_cli, rx_from_ws)      = unbounded::<WsEvent>();
+        let (stop_sender, stop)          = oneshot::channel();
+        let tls = TlsConnector::builder()
+            .add_root_certificate(Certificate::from_pem(&cert).unwrap())
+            .build()
+            .unwrap();
+
+        let sess = Session {
+            host:          format!("https://localhost:{port}"),
+            client:        Client::new(),
+            token:         Some("token".to_string()),
+            show_name:     Some("tester".to_string()),
+            content_type:  "application/json".to_string(),
+            tls:           Some(tls),
+            store:         Arc::new(scratch_store(None)),
+            to_cli:        tx_to_cli,
+        };
+
+        let chat = tokio::spawn(async move { sess.chat_connect("room", rx_from_cli, stop).await });
+        tx_to_ws.send("hi".to_string()).unwrap();
+
+        let events = tokio::task::spawn_blocking(move || {
+            let mut events = Vec::new();
+            while let Ok(ev) = rx_from_ws.recv_timeout(Duration::from_secs(10)) {
+                let done = matches!(ev, WsEvent::Message(_));
+                events.push(ev);
+                if done { break }
+            }
+            (events, rx_from_ws)
+        });
+        let (events, rx_from_ws) = events.await.unwrap();
+        assert!(matches!(events.first(), Some(WsEvent::State(ConnState::Connecting))));
+        assert!(events.iter().any(|ev| matches!(ev, WsEvent::State(ConnState::Connected))));
+        assert!(matches!(events.last(), Some(WsEvent::Message(msg)) if msg.body == "echo: hi"));
+
+        stop_sender.send(()).unwrap();
+        chat.await.unwrap().unwrap();
+        assert!(matches!(rx_from_ws.try_recv(), Ok(WsEvent::State(ConnState::Offline))));
+    }
+
+    // Plain HTTP server answering each path with a canned body, unknown paths get a 404
+    pub(crate) async fn canned_server(routes: Vec<(&'static str, &'static str)>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            while let Ok((mut tcp, _)) = listener.accept().await {
+                let mut buf = vec![0u8; 4096];
+                let read = tcp.read(&mut buf).await.unwrap();
+                let head = String::from_utf8_lossy(&buf[..read]).to_string();
+                let path = head.split_whitespace().nth(1).unwrap_or("").to_string();
+                let reply = match routes.iter().find(|(p, _)| *p == path) {
+                    Some((_, body)) => format!(
+                        "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{body}",
+                        body.len(),
+                    ),
+                    None => "HTTP/1.1 404 Not Found\r\ncontent-length: 0\r\nconnection: close\r\n\r\n".to_string(),
+                };
+                tcp.write_all(reply.as_bytes()).await.unwrap();
+            }
+        });
+        format!("http://127.0.0.1:{port}")
+    }
+
+    fn http_session(host: String) -> Session {
+        let (tx_to_cli, _) = unbounded::<WsEvent>();
+        Session {
+            host,
+            client:        Client::new(),
+            token:         Some("token".to_string()),
+            show_name:     Some("tester".to_string()),
+            content_type:  "application/json".to_string(),
+            tls:           None,
+            store:         Arc::new(scratch_store(None)),
+            to_cli:        tx_to_cli,
+        }
+    }
+
+    #[tokio::test]
+    async fn room_list_falls_back_to_public_rooms() {
+        let publist = r#"{"status":"ok","data":{"names":["lobby"],"hashes":["h1"]}}"#;
+        let mylist  = r#"{"status":"ok","data":{"names":["den","lobby"],"hashes":["h2","h1"]}}"#;
+
+        let sess  = http_session(canned_server(vec![("/rooms/publist", publist)]).await);
+        let rooms = sess.room_list().await.unwrap();
+        assert_eq!(rooms.names, ["lobby"]);
+        assert!(rooms.problem.unwrap().contains("404"));
+
+        // Without publics flags only rooms on the public list count as public
+        let sess  = http_session(canned_server(vec![("/rooms/publist", publist), ("/rooms/mylist", mylist)]).await);
+        let rooms = sess.room_list().await.unwrap();
+        assert_eq!(rooms.names, ["den", "lobby"]);
+        assert_eq!(rooms.visibility, [Visibility::Private, Visibility::Public]);
+        assert!(rooms.problem.is_none());
+    }
+
+    #[tokio::test]
+    async fn failures_map_to_their_session_errors() {
+        let host = canned_server(vec![
+            ("/users/ping",   r#"{"status":"error","error":"Token expired"}"#),
+            ("/users/logout", "<html>not json</html>"),
+        ]).await;
+        let sess = http_session(host.clone());
+
+        match sess.ping().await {
+            Err(SessionError::Server(msg)) => assert_eq!(msg, "Token expired"),
+            other                          => panic!("{other:?}"),
+        }
+        assert!(matches!(sess.logout().await, Err(SessionError::Decode(_))));
+        // Unknown path answers 404 with an empty body
+        match sess.room_publist().await {
+            Err(SessionError::Status { code, body }) => assert_eq!((code.as_u16(), body.as_str()), (404, "")),
+            other                                    => panic!("{other:?}"),
+        }
+
+        let sess = Session { token: None, ..http_session(host) };
+        assert!(matches!(sess.ping().await, Err(SessionError::MissingToken)));
+
+        // Nothing listens once the listener is gone
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let host = format!("http://{}", listener.local_addr().unwrap());
+        drop(listener);
+        assert!(matches!(http_session(host).ping().await, Err(SessionError::Transport(_))));
+    }
+
+    #[tokio::test]
+    async fn login_keeps_the_token_only_when_remembered() {
+        let login = r#"{"status":"ok","data":{"token":"fresh"}}"#;
+        let sess  = http_session(canned_server(vec![("/auth/login", login)]).await);
+
+        sess.login("tester", "secret123", true).await.unwrap();
+        assert_eq!(sess.store.load().unwrap().unwrap().token, "fresh");
+
+        // Forgetting also drops what an earlier login saved
+        let reply = sess.login("tester", "secret123", false).await.unwrap();
+        assert_eq!(reply.token, "fresh");
+        assert_eq!(sess.store.load().unwrap(), None);
+    }
+
+    #[test]
+    fn room_lists_merge_without_repeats() {
+        let list = |rooms: &[(&str, Visibility)]| RoomList {
+            names:      rooms.iter().map(|(n, _)| n.to_string()).collect(),
+            hashes:     rooms.iter().map(|(n, _)| format!("#{n}")).collect(),
+            visibility: rooms.iter().map(|(_, v)| *v).collect(),
+            problem:    None,
+        };
+        let mine   = list(&[("den", Visibility::Private), ("lobby", Visibility::Public)]);
+        let public = list(&[("lobby", Visibility::Public), ("dev", Visibility::Public)]);
+
+        let rooms = mine.merge(public);
+        assert_eq!(rooms.names, ["den", "lobby", "dev"]);
+        assert_eq!(rooms.visibility, [Visibility::Private, Visibility::Public, Visibility::Public]);
+        assert_eq!(Visibility::from_label("Private"), Some(Visibility::Private));
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn messages_typed_offline_go_out_after_reconnect() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        // First socket is dropped right away, the second answers what it gets
+        tokio::spawn(async move {
+            let (tcp, _) = listener.accept().await.unwrap();
+            drop(tokio_tungstenite::accept_async(tcp).await.unwrap());
+
+            let (tcp, _) = listener.accept().await.unwrap();
+            let mut ws = tokio_tungstenite::accept_async(tcp).await.unwrap();
+            while let Some(Ok(msg)) = ws.next().await {
+                if let Message::Text(text) = msg {
+                    ws.send(Message::Text(format!("got: {text}").into())).await.unwrap();
                 }
             }
+        });
 
-            if let Ok(msg) = socket.read() {
-                match msg {
-                    Message::Text(text) => {
-                        println!("{}", text.clone().to_string());
-                        self.to_cli.send(text.to_string())?;
-                    }
-                    Message::Close(_) => {
-                        println!("WebSocket connection closed");
-                        break
-                    }
-                    _ => {}
+        let (tx_to_ws, rx_from_cli)      = unbounded_channel::<String>();
+        let (tx_to_cli, rx_from_ws)      = unbounded::<WsEvent>();
+        let (stop_sender, stop)          = oneshot::channel();
+        let sess = Session {
+            host:          format!("http://127.0.0.1:{port}"),
+            client:        Client::new(),
+            token:         Some("token".to_string()),
+            show_name:     Some("tester".to_string()),
+            content_type:  "application/json".to_string(),
+            tls:           None,
+            store:         Arc::new(scratch_store(None)),
+            to_cli:        tx_to_cli,
+        };
+        let chat = tokio::spawn(async move { sess.chat_connect("room", rx_from_cli, stop).await });
+
+        let events = tokio::task::spawn_blocking(move || {
+            let mut events = Vec::new();
+            while let Ok(ev) = rx_from_ws.recv_timeout(Duration::from_secs(10)) {
+                // Typed while the socket is down
+                if matches!(ev, WsEvent::State(ConnState::Reconnecting(1))) {
+                    tx_to_ws.send("queued".to_string()).unwrap();
                 }
+                let done = matches!(ev, WsEvent::Message(_));
+                events.push(ev);
+                if done { break }
             }
+            (events, rx_from_ws)
+        });
+        let (events, rx_from_ws) = events.await.unwrap();
+        let states: Vec<ConnState> = events.iter()
+            .filter_map(|ev| match ev {
+                WsEvent::State(state) => Some(*state),
+                _                     => None,
+            })
+            .collect();
+        assert_eq!(states, [
+            ConnState::Connecting,
+            ConnState::Connected,
+            ConnState::Reconnecting(1),
+            ConnState::Connected,
+        ]);
+        assert!(matches!(events.last(), Some(WsEvent::Message(msg)) if msg.body == "got: queued"));
+
+        stop_sender.send(()).unwrap();
+        chat.await.unwrap().unwrap();
+        assert!(matches!(rx_from_ws.try_recv(), Ok(WsEvent::State(ConnState::Offline))));
+    }
 
-            socket.flush()?;
-            std::thread::sleep(std::time::Duration::from_millis(10));
-        }
-        Ok(())
+    #[tokio::test]
+    async fn refused_handshake_isnt_retried() {
+        // Turns the upgrade down the way a server rejecting the token would
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let host = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            let (mut tcp, _) = listener.accept().await.unwrap();
+            let mut buf = [0; 4096];
+            let _ = tcp.read(&mut buf).await;
+            tcp.write_all(b"HTTP/1.1 401 Unauthorized\r\ncontent-length: 0\r\n\r\n").await.unwrap();
+        });
+
+        let (tx_to_cli, rx_from_ws) = unbounded::<WsEvent>();
+        let sess = Session { to_cli: tx_to_cli, ..http_session(host.clone()) };
+        let (_to_ws, from_cli) = unbounded_channel::<String>();
+        let (_stop, stop)      = oneshot::channel();
+        let err = sess.chat_connect("room", from_cli, stop).await.unwrap_err();
+        assert!(err.is_fatal(), "{err}");
+        let events: Vec<WsEvent> = rx_from_ws.try_iter().collect();
+        assert!(matches!(events[..], [WsEvent::State(ConnState::Connecting), WsEvent::State(ConnState::Offline)]));
+
+        // Without a token it never gets as far as the server
+        let sess = Session { token: None, ..http_session(host) };
+        let (_to_ws, from_cli) = unbounded_channel::<String>();
+        let (_stop, stop)      = oneshot::channel();
+        assert!(matches!(sess.chat_connect("room", from_cli, stop).await, Err(SessionError::MissingToken)));
+    }
+
+    #[tokio::test]
+    async fn leaving_while_offline_reports_unsent_messages() {
+        // Nothing listens here, so the chat sits in backoff
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let host = format!("http://{}", listener.local_addr().unwrap());
+        drop(listener);
+
+        let (tx_to_cli, rx_from_ws) = unbounded::<WsEvent>();
+        let sess = Session { to_cli: tx_to_cli, ..http_session(host) };
+        let (to_ws, from_cli)   = unbounded_channel::<String>();
+        let (stop_sender, stop) = oneshot::channel();
+        to_ws.send("one".to_string()).unwrap();
+        to_ws.send("two".to_string()).unwrap();
+        stop_sender.send(()).unwrap();
+        sess.chat_connect("room", from_cli, stop).await.unwrap();
+
+        let events: Vec<WsEvent> = rx_from_ws.try_iter().collect();
+        assert!(events.iter().any(|ev| matches!(ev, WsEvent::Problem(p) if p == "2 unsent message(s) dropped")));
+        assert!(matches!(events.last(), Some(WsEvent::State(ConnState::Offline))));
     }
 }
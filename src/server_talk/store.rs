@@ -0,0 +1,215 @@
+use argon2::Argon2;
+use base64::{engine::general_purpose::STANDARD as B64, Engine};
+use chacha20poly1305::{
+    aead::{rand_core::RngCore, Aead, AeadCore, KeyInit, OsRng},
+    ChaCha20Poly1305, Key, Nonce,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{self, OpenOptions},
+    io::{ErrorKind, Write},
+    path::PathBuf,
+};
+use crate::settings::config_dir;
+use super::errors::SessionError;
+
+
+// What survives between runs so users don't log in every time
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Credentials {
+    pub token:     String,
+    pub show_name: String,
+}
+
+// Anything able to keep credentials around, OS keyrings can plug in here
+pub trait CredentialStore: Send + Sync {
+    fn load(&self) -> Result<Option<Credentials>, SessionError>;
+    fn save(&self, creds: &Credentials) -> Result<(), SessionError>;
+    fn clear(&self) -> Result<(), SessionError>;
+}
+
+// Layout of the file on disk, sealed when a passphrase is set
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum StoreFile {
+    Sealed { salt: String, nonce: String, sealed: String },
+    Plain(Credentials),
+}
+
+// Default backend, a single user-only file under the XDG config dir
+pub struct FileStore {
+    path:       PathBuf,
+    passphrase: Option<String>,
+}
+
+impl FileStore {
+    pub fn new(path: PathBuf, passphrase: Option<String>) -> Self {
+        Self { path, passphrase }
+    }
+
+    // Each profile keeps its own login
+    pub fn profile_path(profile: &str) -> PathBuf {
+        config_dir().join("credentials").join(format!("{profile}.json"))
+    }
+
+    fn key(passphrase: &str, salt: &[u8]) -> Result<Key, SessionError> {
+        let mut key = Key::default();
+        Argon2::default()
+            .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+            .map_err(|e| SessionError::Store(format!("can't derive key: {e}")))?;
+        Ok(key)
+    }
+
+    fn seal(passphrase: &str, creds: &Credentials) -> Result<StoreFile, SessionError> {
+        let mut salt = [0u8; 16];
+        OsRng.fill_bytes(&mut salt);
+        let cipher = ChaCha20Poly1305::new(&Self::key(passphrase, &salt)?);
+        let nonce  = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let plain  = serde_json::to_vec(creds).map_err(|e| SessionError::Store(e.to_string()))?;
+        let sealed = cipher
+            .encrypt(&nonce, plain.as_slice())
+            .map_err(|_| SessionError::Store("can't encrypt credentials".to_string()))?;
+
+        Ok(StoreFile::Sealed {
+            salt:   B64.encode(salt),
+            nonce:  B64.encode(nonce),
+            sealed: B64.encode(sealed),
+        })
+    }
+
+    fn open(passphrase: &str, salt: &str, nonce: &str, sealed: &str) -> Result<Credentials, SessionError> {
+        let decode = |s: &str| B64.decode(s).map_err(|e| SessionError::Store(format!("corrupt credentials file: {e}")));
+        let (salt, nonce, sealed) = (decode(salt)?, decode(nonce)?, decode(sealed)?);
+        if nonce.len() != 12 {
+            return Err(SessionError::Store("corrupt credentials file: bad nonce".to_string()))
+        }
+
+        let cipher = ChaCha20Poly1305::new(&Self::key(passphrase, &salt)?);
+        let plain  = cipher
+            .decrypt(Nonce::from_slice(&nonce), sealed.as_slice())
+            .map_err(|_| SessionError::Store("wrong passphrase for credentials".to_string()))?;
+        serde_json::from_slice(&plain).map_err(|e| SessionError::Store(format!("corrupt credentials file: {e}")))
+    }
+
+    fn is_sealed(&self) -> bool {
+        let raw = fs::read_to_string(&self.path).unwrap_or_default();
+        matches!(serde_json::from_str(&raw), Ok(StoreFile::Sealed { .. }))
+    }
+}
+
+impl CredentialStore for FileStore {
+    fn load(&self) -> Result<Option<Credentials>, SessionError> {
+        let raw = match fs::read_to_string(&self.path) {
+            Ok(raw)                                   => raw,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e)                                    => return Err(SessionError::Store(e.to_string())),
+        };
+
+        let file: StoreFile = serde_json::from_str(&raw)
+            .map_err(|e| SessionError::Store(format!("corrupt credentials file: {e}")))?;
+        match (file, &self.passphrase) {
+            (StoreFile::Plain(creds), _) => Ok(Some(creds)),
+            (StoreFile::Sealed { salt, nonce, sealed }, Some(pass)) => {
+                Self::open(pass, &salt, &nonce, &sealed).map(Some)
+            },
+            (StoreFile::Sealed { .. }, None) => {
+                Err(SessionError::Store("credentials are encrypted, set TOKEN_PASSPHRASE".to_string()))
+            },
+        }
+    }
+
+    fn save(&self, creds: &Credentials) -> Result<(), SessionError> {
+        let file = match &self.passphrase {
+            Some(pass) => Self::seal(pass, creds)?,
+            // Never trade an encrypted file for a plain one behind the user's back
+            None if self.is_sealed() => {
+                return Err(SessionError::Store("credentials are encrypted, set TOKEN_PASSPHRASE".to_string()))
+            },
+            None       => StoreFile::Plain(creds.clone()),
+        };
+        let raw = serde_json::to_string_pretty(&file).map_err(|e| SessionError::Store(e.to_string()))?;
+        let io  = |e: std::io::Error| SessionError::Store(format!("{}: {e}", self.path.display()));
+
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir).map_err(io)?;
+        }
+
+        // Written next to the old one then moved over it, so a crash never leaves half a file
+        let tmp = self.path.with_extension("tmp");
+        let mut options = OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        let mut out = options.open(&tmp).map_err(io)?;
+        out.write_all(raw.as_bytes()).map_err(io)?;
+        out.sync_all().map_err(io)?;
+        fs::rename(&tmp, &self.path).map_err(io)
+    }
+
+    fn clear(&self) -> Result<(), SessionError> {
+        match fs::remove_file(&self.path) {
+            Err(e) if e.kind() != ErrorKind::NotFound => Err(SessionError::Store(e.to_string())),
+            _                                         => Ok(()),
+        }
+    }
+}
+
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::env;
+
+    // A store in its own temp directory so tests never touch the real one
+    pub(crate) fn scratch_store(passphrase: Option<&str>) -> FileStore {
+        let dir = env::temp_dir().join(format!("termochat-{:016x}", fastrand::u64(..)));
+        FileStore::new(dir.join("credentials.json"), passphrase.map(str::to_string))
+    }
+
+    fn creds() -> Credentials {
+        Credentials { token: "secret-token".to_string(), show_name: "tester".to_string() }
+    }
+
+    #[test]
+    fn missing_file_is_no_credentials() {
+        assert_eq!(scratch_store(None).load().unwrap(), None);
+    }
+
+    #[test]
+    fn plain_round_trip_is_user_only() {
+        let store = scratch_store(None);
+        store.save(&creds()).unwrap();
+        assert_eq!(store.load().unwrap(), Some(creds()));
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&store.path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        store.clear().unwrap();
+        assert_eq!(store.load().unwrap(), None);
+    }
+
+    #[test]
+    fn sealed_file_needs_the_passphrase() {
+        let store = scratch_store(Some("hunter2"));
+        store.save(&creds()).unwrap();
+        assert!(!fs::read_to_string(&store.path).unwrap().contains("secret-token"));
+        assert_eq!(store.load().unwrap(), Some(creds()));
+
+        let wrong = FileStore::new(store.path.clone(), Some("nope".to_string()));
+        assert!(matches!(wrong.load(), Err(SessionError::Store(_))));
+        let none = FileStore::new(store.path.clone(), None);
+        assert!(matches!(none.load(), Err(SessionError::Store(_))));
+
+        // Without the passphrase the sealed file isn't replaced by a plain one
+        assert!(matches!(none.save(&creds()), Err(SessionError::Store(_))));
+        assert!(!fs::read_to_string(&store.path).unwrap().contains("secret-token"));
+        assert_eq!(store.load().unwrap(), Some(creds()));
+    }
+}
@@ -0,0 +1,213 @@
+use crate::tui::theme::{Theme, ThemeConfig};
+use chrono::format::{Item, StrftimeItems};
+use serde::Deserialize;
+use std::{
+    fs,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+use super::{config_dir, errors::SettingsError, keymap::Keymap};
+
+
+// Everything config.toml can set, missing keys fall back to the defaults below
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub server:        ServerConfig,
+    pub theme:         ThemeConfig,
+    pub layout:        LayoutConfig,
+    pub keys:          toml::Table,     // As written, see keymap
+    pub timestamps:    TimestampConfig,
+    pub notifications: NotifyConfig,
+    #[serde(skip)]
+    pub keymap:        Keymap,          // Built from the defaults and keys
+    #[serde(skip)]
+    raw:               toml::Table,     // As written, profile preferences are merged into it
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub host: Option<String>, // Used when there is no profiles.toml
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LayoutConfig {
+    pub rooms_width:    u16, // Percent of the screen, chat takes the rest
+    pub max_input_rows: u16, // Typing box grows up to this, then scrolls
+}
+
+impl Default for LayoutConfig {
+    fn default() -> Self {
+        Self { rooms_width: 30, max_input_rows: 5 }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TimestampConfig {
+    pub show:   bool,
+    pub format: String, // strftime style, in local time
+}
+
+impl Default for TimestampConfig {
+    fn default() -> Self {
+        Self { show: true, format: "%H:%M".to_string() }
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NotifyConfig {
+    pub bell:          bool, // Ring the terminal bell on messages from others
+    pub mentions_only: bool, // ... but only when they mention us
+}
+
+impl Config {
+    pub fn default_path() -> PathBuf {
+        config_dir().join("config.toml")
+    }
+
+    // A missing default file means defaults, a missing --config file is an error
+    pub fn load(path: Option<&Path>) -> Result<Self, SettingsError> {
+        let explicit = path.is_some();
+        let path = path.map(Path::to_path_buf).unwrap_or_else(Self::default_path);
+
+        let raw = match fs::read_to_string(&path) {
+            Ok(raw) => raw,
+            Err(e) if e.kind() == ErrorKind::NotFound && !explicit => return Ok(Self::default()),
+            Err(err) => return Err(SettingsError::Io { path, err }),
+        };
+        let table: toml::Table = toml::from_str(&raw)
+            .map_err(|e| SettingsError::Parse { path: path.clone(), msg: e.to_string() })?;
+
+        Self::from_table(table).map_err(|msg| SettingsError::Parse { path, msg })
+    }
+
+    fn from_table(table: toml::Table) -> Result<Self, String> {
+        let mut config: Config = toml::Value::Table(table.clone())
+            .try_into()
+            .map_err(|e: toml::de::Error| e.message().to_string())?;
+        config.raw = table;
+
+        let mut problems = config.problems();
+        match Keymap::from_config(&config.keys) {
+            Ok(keymap) => config.keymap = keymap,
+            Err(keys)  => problems.extend(keys),
+        }
+        if problems.is_empty() {
+            Ok(config)
+        } else {
+            Err(problems.join(", "))
+        }
+    }
+
+    // Same config with a profile's preferences laid over it
+    pub fn for_profile(&self, name: &str, preferences: &toml::Table) -> Result<Self, SettingsError> {
+        let mut table = self.raw.clone();
+        merge(&mut table, preferences);
+        Self::from_table(table)
+            .map_err(|msg| SettingsError::Invalid(format!("Preferences of profile \"{name}\": {msg}")))
+    }
+
+    // Values that parse fine but can't be used
+    fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+
+        if !(10..=90).contains(&self.layout.rooms_width) {
+            problems.push("layout.rooms_width must be between 10 and 90".to_string());
+        }
+        if !(1..=20).contains(&self.layout.max_input_rows) {
+            problems.push("layout.max_input_rows must be between 1 and 20".to_string());
+        }
+        if StrftimeItems::new(&self.timestamps.format).any(|i| matches!(i, Item::Error)) {
+            problems.push(format!("timestamps.format \"{}\" isn't a valid time format", self.timestamps.format));
+        }
+        if let Err(e) = Theme::from_config(&self.theme) {
+            problems.push(format!("theme: {e}"));
+        }
+        if let Some(host) = &self.server.host {
+            if !host.starts_with("http://") && !host.starts_with("https://") {
+                problems.push("server.host must start with http:// or https://".to_string());
+            }
+        }
+        problems
+    }
+}
+
+// Tables are merged key by key, anything else is replaced
+fn merge(base: &mut toml::Table, over: &toml::Table) {
+    for (key, value) in over {
+        match (base.get_mut(key), value) {
+            (Some(toml::Value::Table(b)), toml::Value::Table(o)) => merge(b, o),
+            _ => {
+                base.insert(key.clone(), value.clone());
+            },
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(raw: &str) -> Result<Config, String> {
+        Config::from_table(toml::from_str(raw).unwrap())
+    }
+
+    #[test]
+    fn empty_file_is_all_defaults() {
+        let config = parse("").unwrap();
+        assert_eq!(config.layout.rooms_width, 30);
+        assert_eq!(config.theme.name, None);
+        assert!(config.timestamps.show);
+    }
+
+    #[test]
+    fn reads_colors_and_sections() {
+        let config = parse(r##"
+            [theme]
+            name = "light"
+            border = "#112233"
+
+            [layout]
+            rooms_width = 25
+
+            [notifications]
+            bell = true
+        "##).unwrap();
+        assert_eq!(config.theme.name.as_deref(), Some("light"));
+        assert_eq!(config.theme.colors["border"], "#112233");
+        assert_eq!(config.layout.rooms_width, 25);
+        assert!(config.notifications.bell);
+    }
+
+    #[test]
+    fn reports_every_bad_value() {
+        let err = parse(r#"
+            layout = { rooms_width = 95, max_input_rows = 0 }
+            timestamps = { format = "%Q" }
+        "#).unwrap_err();
+        assert!(err.contains("rooms_width"));
+        assert!(err.contains("max_input_rows"));
+        assert!(err.contains("timestamps.format"));
+
+        assert!(parse("[theme]\nborder = \"no such color\"").unwrap_err().contains("no such color"));
+        assert!(parse("[layuot]").is_err());
+        assert!(parse("[keys.chat]\nscroll_top = \"q\"").unwrap_err().contains("clashes"));
+    }
+
+    #[test]
+    fn profile_preferences_override_config() {
+        let config = parse("[layout]\nrooms_width = 25\nmax_input_rows = 3").unwrap();
+        let prefs: toml::Table = toml::from_str("layout = { rooms_width = 40 }").unwrap();
+        let merged = config.for_profile("work", &prefs).unwrap();
+        assert_eq!(merged.layout.rooms_width, 40);
+        assert_eq!(merged.layout.max_input_rows, 3);
+
+        let bad: toml::Table = toml::from_str("layout = { rooms_width = 1 }").unwrap();
+        assert!(config.for_profile("work", &bad).is_err());
+    }
+}
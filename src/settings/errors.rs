@@ -0,0 +1,28 @@
+use std::{fmt, io, path::PathBuf};
+
+
+#[derive(Debug)]
+pub enum SettingsError {
+    Io { path: PathBuf, err: io::Error },             // File exists but can't be read
+    Parse { path: PathBuf, msg: String },             // Not valid TOML or wrong shape
+    Invalid(String),                                  // Parsed fine, makes no sense
+}
+
+impl fmt::Display for SettingsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SettingsError::Io { path, err }    => write!(f, "Can't read {}: {err}", path.display()),
+            SettingsError::Parse { path, msg } => write!(f, "Bad {}: {msg}", path.display()),
+            SettingsError::Invalid(msg)        => write!(f, "{msg}"),
+        }
+    }
+}
+
+impl std::error::Error for SettingsError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SettingsError::Io { err, .. } => Some(err),
+            _                             => None,
+        }
+    }
+}
@@ -0,0 +1,557 @@
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use std::fmt;
+
+
+// Everything a key can be bound to
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Action {
+    Quit,
+    Help,
+    NextTheme,
+    FocusNext,
+    FocusPrev,
+    Activate,
+    OpenProfiles,
+    OpenAccount,
+    SelectPrev,
+    SelectNext,
+    Confirm,
+    Back,
+    CreateRoom,
+    EditRoom,
+    CloseRoom,
+    ScrollUp,
+    ScrollDown,
+    PageUp,
+    PageDown,
+    ScrollTop,
+    ScrollBottom,
+    FieldNext,
+    FieldPrev,
+    EditField,
+    SubmitForm,
+    Reveal,
+    CursorLeft,
+    CursorRight,
+    WordLeft,
+    WordRight,
+    LineUp,
+    LineDown,
+    DeleteChar,
+    DeleteWord,
+    NormalMode,
+    Send,
+}
+
+impl Action {
+    // Name used in config.toml and what the help shows
+    pub fn info(self) -> (&'static str, &'static str) {
+        match self {
+            Action::Quit         => ("quit",          "Quit"),
+            Action::Help         => ("help",          "Show these keys"),
+            Action::NextTheme    => ("next_theme",    "Switch to the next theme"),
+            Action::FocusNext    => ("focus_next",    "Focus next pane"),
+            Action::FocusPrev    => ("focus_prev",    "Focus previous pane"),
+            Action::Activate     => ("activate",      "Enter room / start typing"),
+            Action::OpenProfiles => ("profiles",      "Switch profile"),
+            Action::OpenAccount  => ("account",       "Account settings"),
+            Action::SelectPrev   => ("select_prev",   "Previous entry"),
+            Action::SelectNext   => ("select_next",   "Next entry"),
+            Action::Confirm      => ("confirm",       "Open selected entry"),
+            Action::Back         => ("back",          "Go back"),
+            Action::CreateRoom   => ("create_room",   "Create a room"),
+            Action::EditRoom     => ("edit_room",     "Edit selected room"),
+            Action::CloseRoom    => ("close_room",    "Close selected room"),
+            Action::ScrollUp     => ("scroll_up",     "Scroll up a line"),
+            Action::ScrollDown   => ("scroll_down",   "Scroll down a line"),
+            Action::PageUp       => ("page_up",       "Scroll up a page"),
+            Action::PageDown     => ("page_down",     "Scroll down a page"),
+            Action::ScrollTop    => ("scroll_top",    "Jump to oldest message"),
+            Action::ScrollBottom => ("scroll_bottom", "Jump to newest message"),
+            Action::FieldNext    => ("field_next",    "Next field"),
+            Action::FieldPrev    => ("field_prev",    "Previous field"),
+            Action::EditField    => ("edit_field",    "Edit field / flip toggle"),
+            Action::SubmitForm   => ("submit_form",   "Submit form"),
+            Action::Reveal       => ("reveal",        "Show passwords for a moment"),
+            Action::CursorLeft   => ("cursor_left",   "Cursor left"),
+            Action::CursorRight  => ("cursor_right",  "Cursor right"),
+            Action::WordLeft     => ("word_left",     "Word left"),
+            Action::WordRight    => ("word_right",    "Word right"),
+            Action::LineUp       => ("line_up",       "Previous line"),
+            Action::LineDown     => ("line_down",     "Next line"),
+            Action::DeleteChar   => ("delete_char",   "Delete char before cursor"),
+            Action::DeleteWord   => ("delete_word",   "Delete word before cursor"),
+            Action::NormalMode   => ("normal_mode",   "Back to normal mode"),
+            Action::Send         => ("send",          "Send (trailing \\ starts a new line)"),
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        self.info().0
+    }
+
+    pub fn describe(self) -> &'static str {
+        self.info().1
+    }
+}
+
+// Groups of bindings, more than one can be active at a time
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Context {
+    Global,   // Normal mode, every screen
+    Main,     // Normal mode on the chat screen
+    Rooms,
+    Chat,
+    Choose,   // Sign up / log in picker
+    Form,
+    Profiles,
+    Account,  // Account settings menu
+    Insert,
+}
+
+impl Context {
+    pub const ALL: [Context; 9] = [
+        Context::Global, Context::Main, Context::Rooms, Context::Chat,
+        Context::Choose, Context::Form, Context::Profiles, Context::Account, Context::Insert,
+    ];
+
+    // Contexts that are active together, most specific first
+    const STACKS: [&'static [Context]; 8] = [
+        &[Context::Rooms, Context::Main, Context::Global],
+        &[Context::Chat, Context::Main, Context::Global],
+        &[Context::Main, Context::Global],
+        &[Context::Choose, Context::Global],
+        &[Context::Form, Context::Global],
+        &[Context::Profiles, Context::Global],
+        &[Context::Account, Context::Global],
+        &[Context::Insert],
+    ];
+
+    pub fn title(self) -> &'static str {
+        match self {
+            Context::Global   => "Everywhere",
+            Context::Main     => "Chat screen",
+            Context::Rooms    => "Rooms",
+            Context::Chat     => "Messages",
+            Context::Choose   => "Sign up / Log in",
+            Context::Form     => "Forms",
+            Context::Profiles => "Profiles",
+            Context::Account  => "Account",
+            Context::Insert   => "Typing",
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Context::Global   => "global",
+            Context::Main     => "main",
+            Context::Rooms    => "rooms",
+            Context::Chat     => "chat",
+            Context::Choose   => "choose",
+            Context::Form     => "form",
+            Context::Profiles => "profiles",
+            Context::Account  => "account",
+            Context::Insert   => "insert",
+        }
+    }
+
+    // Bindings we ship with, also decides which actions make sense where
+    fn defaults(self) -> &'static [(Action, &'static [&'static str])] {
+        match self {
+            Context::Global => &[
+                (Action::Quit,         &["q"]),
+                (Action::Help,         &["?", "F1"]),
+                (Action::NextTheme,    &["C-t"]),
+            ],
+            Context::Main => &[
+                (Action::FocusNext,    &["Tab"]),
+                (Action::FocusPrev,    &["BackTab"]),
+                (Action::Activate,     &["Enter"]),
+                (Action::OpenProfiles, &["p"]),
+                (Action::OpenAccount,  &["a"]),
+            ],
+            Context::Rooms => &[
+                (Action::SelectPrev,   &["Up"]),
+                (Action::SelectNext,   &["Down"]),
+                (Action::CreateRoom,   &["c"]),
+                (Action::EditRoom,     &["e"]),
+                (Action::CloseRoom,    &["x"]),
+            ],
+            Context::Chat => &[
+                (Action::ScrollUp,     &["k"]),
+                (Action::ScrollDown,   &["j"]),
+                (Action::PageUp,       &["PageUp"]),
+                (Action::PageDown,     &["PageDown"]),
+                (Action::ScrollTop,    &["Home", "g g"]),
+                (Action::ScrollBottom, &["End", "G"]),
+            ],
+            Context::Choose => &[
+                (Action::SelectPrev,   &["Up"]),
+                (Action::SelectNext,   &["Down"]),
+                (Action::Confirm,      &["Enter"]),
+                (Action::OpenProfiles, &["p"]),
+            ],
+            Context::Form => &[
+                (Action::FieldNext,    &["Tab"]),
+                (Action::FieldPrev,    &["BackTab"]),
+                (Action::EditField,    &["Enter"]),
+                (Action::SubmitForm,   &["Space"]),
+                (Action::Reveal,       &["C-r"]),
+                (Action::Back,         &["Esc"]),
+            ],
+            Context::Profiles => &[
+                (Action::SelectPrev,   &["Up"]),
+                (Action::SelectNext,   &["Down"]),
+                (Action::Confirm,      &["Enter"]),
+                (Action::Back,         &["Esc"]),
+            ],
+            Context::Account => &[
+                (Action::SelectPrev,   &["Up"]),
+                (Action::SelectNext,   &["Down"]),
+                (Action::Confirm,      &["Enter"]),
+                (Action::Back,         &["Esc"]),
+            ],
+            Context::Insert => &[
+                (Action::CursorLeft,   &["Left"]),
+                (Action::CursorRight,  &["Right"]),
+                (Action::WordLeft,     &["S-Left"]),
+                (Action::WordRight,    &["S-Right"]),
+                (Action::LineUp,       &["Up"]),
+                (Action::LineDown,     &["Down"]),
+                (Action::DeleteChar,   &["Backspace"]),
+                (Action::DeleteWord,   &["C-w"]),
+                (Action::NormalMode,   &["Esc"]),
+                (Action::Send,         &["Enter"]),
+                (Action::Reveal,       &["C-r"]),
+                (Action::Help,         &["F1"]),
+            ],
+        }
+    }
+}
+
+// A single key press, shift is folded into the char for letters
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Key {
+    pub code: KeyCode,
+    pub mods: KeyModifiers,
+}
+
+impl Key {
+    pub fn new(code: KeyCode, mods: KeyModifiers) -> Self {
+        let mods = match code {
+            KeyCode::Char(_) | KeyCode::BackTab => mods - KeyModifiers::SHIFT,
+            _                                   => mods,
+        };
+        match (code, mods.contains(KeyModifiers::SHIFT)) {
+            (KeyCode::Tab, true) => Self { code: KeyCode::BackTab, mods: mods - KeyModifiers::SHIFT },
+            _                    => Self { code, mods },
+        }
+    }
+
+    // Written like "q", "C-w", "S-Right", "PageUp" or "Space"
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let prefixes = [
+            ("C-", KeyModifiers::CONTROL),
+            ("S-", KeyModifiers::SHIFT),
+            ("A-", KeyModifiers::ALT),
+        ];
+        let mut mods = KeyModifiers::NONE;
+        let mut rest = s;
+        // A bare "C-" is left alone to be reported as an unknown key
+        while let Some((m, after)) = prefixes.iter().find_map(|(prefix, m)| {
+            rest.strip_prefix(prefix).filter(|r| !r.is_empty()).map(|r| (*m, r))
+        }) {
+            mods |= m;
+            rest = after;
+        }
+
+        let mut chars = rest.chars();
+        let code = match (chars.next(), chars.next()) {
+            (Some(c), None) => KeyCode::Char(c),
+            _ => match rest.to_lowercase().as_str() {
+                "tab"       => KeyCode::Tab,
+                "backtab"   => KeyCode::BackTab,
+                "enter"     => KeyCode::Enter,
+                "esc"       => KeyCode::Esc,
+                "space"     => KeyCode::Char(' '),
+                "backspace" => KeyCode::Backspace,
+                "delete"    => KeyCode::Delete,
+                "insert"    => KeyCode::Insert,
+                "up"        => KeyCode::Up,
+                "down"      => KeyCode::Down,
+                "left"      => KeyCode::Left,
+                "right"     => KeyCode::Right,
+                "home"      => KeyCode::Home,
+                "end"       => KeyCode::End,
+                "pageup"    => KeyCode::PageUp,
+                "pagedown"  => KeyCode::PageDown,
+                f => match f.strip_prefix('f').and_then(|n| n.parse::<u8>().ok()) {
+                    Some(n) if (1..=12).contains(&n) => KeyCode::F(n),
+                    _ => return Err(format!("unknown key \"{s}\"")),
+                },
+            },
+        };
+        Ok(Self::new(code, mods))
+    }
+}
+
+impl From<KeyEvent> for Key {
+    fn from(e: KeyEvent) -> Self {
+        Self::new(e.code, e.modifiers)
+    }
+}
+
+impl fmt::Display for Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (m, prefix) in [(KeyModifiers::CONTROL, "C-"), (KeyModifiers::ALT, "A-"), (KeyModifiers::SHIFT, "S-")] {
+            if self.mods.contains(m) {
+                write!(f, "{prefix}")?;
+            }
+        }
+        match self.code {
+            KeyCode::Char(' ') => write!(f, "Space"),
+            KeyCode::Char(c)   => write!(f, "{c}"),
+            KeyCode::F(n)      => write!(f, "F{n}"),
+            code               => write!(f, "{code:?}"),
+        }
+    }
+}
+
+// One or more keys pressed in a row, like "g g"
+pub type Sequence = Vec<Key>;
+
+fn parse_sequence(s: &str) -> Result<Sequence, String> {
+    let keys = s.split_whitespace().map(Key::parse).collect::<Result<Sequence, String>>()?;
+    match keys.is_empty() {
+        true  => Err("empty key".to_string()),
+        false => Ok(keys),
+    }
+}
+
+pub fn show_sequence(seq: &[Key]) -> String {
+    seq.iter().map(Key::to_string).collect::<Vec<String>>().join(" ")
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Binding {
+    pub keys:   Sequence,
+    pub action: Action,
+}
+
+// What the keys pressed so far amount to
+#[derive(Debug, PartialEq)]
+pub enum Lookup {
+    Action(Action),
+    Prefix,         // Part of a longer sequence, wait for more
+    Unbound,
+}
+
+#[derive(Clone, Debug)]
+pub struct Keymap {
+    sections: Vec<(Context, Vec<Binding>)>,
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        Self::from_config(&toml::Table::new()).unwrap_or_else(|_| Self { sections: Vec::new() })
+    }
+}
+
+impl Keymap {
+    // [keys.<context>] tables replace the default keys of the actions they name
+    pub fn from_config(keys: &toml::Table) -> Result<Self, Vec<String>> {
+        let mut problems = Vec::new();
+
+        for name in keys.keys() {
+            if !Context::ALL.iter().any(|c| c.name() == name) {
+                problems.push(format!("keys.{name} isn't a known section"));
+            }
+        }
+
+        let mut sections = Vec::new();
+        for context in Context::ALL {
+            let overrides = match keys.get(context.name()) {
+                Some(toml::Value::Table(t)) => Some(t),
+                Some(_) => {
+                    problems.push(format!("keys.{} must be a table", context.name()));
+                    None
+                },
+                None => None,
+            };
+
+            if let Some(overrides) = overrides {
+                for name in overrides.keys() {
+                    if !context.defaults().iter().any(|(a, _)| a.name() == name) {
+                        problems.push(format!("keys.{}.{name} isn't an action there", context.name()));
+                    }
+                }
+            }
+
+            let mut bindings = Vec::new();
+            for (action, default_keys) in context.defaults() {
+                let written: Vec<String> = match overrides.and_then(|t| t.get(action.name())) {
+                    None => default_keys.iter().map(|k| k.to_string()).collect(),
+                    Some(toml::Value::String(k)) => vec![k.clone()],
+                    Some(toml::Value::Array(ks)) => ks.iter().filter_map(|k| k.as_str().map(str::to_string)).collect(),
+                    Some(_) => {
+                        problems.push(format!("keys.{}.{} must be a key or a list of keys", context.name(), action.name()));
+                        continue
+                    },
+                };
+
+                for k in written {
+                    match parse_sequence(&k) {
+                        Ok(keys) => bindings.push(Binding { keys, action: *action }),
+                        Err(e)   => problems.push(format!("keys.{}.{}: {e}", context.name(), action.name())),
+                    }
+                }
+            }
+            sections.push((context, bindings));
+        }
+
+        let keymap = Self { sections };
+        problems.extend(keymap.conflicts());
+        match problems.is_empty() {
+            true  => Ok(keymap),
+            false => Err(problems),
+        }
+    }
+
+    pub fn bindings(&self, context: Context) -> &[Binding] {
+        self.sections
+            .iter()
+            .find(|(c, _)| *c == context)
+            .map(|(_, b)| b.as_slice())
+            .unwrap_or(&[])
+    }
+
+    // Two bindings reachable at once where one equals or starts the other
+    fn conflicts(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        for stack in Context::STACKS {
+            let reachable: Vec<(Context, &Binding)> = stack
+                .iter()
+                .flat_map(|c| self.bindings(*c).iter().map(move |b| (*c, b)))
+                .collect();
+
+            for (i, (ca, a)) in reachable.iter().enumerate() {
+                for (cb, b) in &reachable[i + 1..] {
+                    let shorter = a.keys.len().min(b.keys.len());
+                    if a.keys[..shorter] != b.keys[..shorter] {
+                        continue
+                    }
+                    let problem = format!(
+                        "\"{}\" ({}.{}) clashes with \"{}\" ({}.{})",
+                        show_sequence(&a.keys), ca.name(), a.action.name(),
+                        show_sequence(&b.keys), cb.name(), b.action.name(),
+                    );
+                    if !problems.contains(&problem) {
+                        problems.push(problem);
+                    }
+                }
+            }
+        }
+        problems
+    }
+
+    // Keys of every action in a context as shown to the user, unbound actions left out
+    pub fn help(&self, context: Context) -> Vec<(String, Action)> {
+        let mut rows: Vec<(String, Action)> = Vec::new();
+        for b in self.bindings(context) {
+            let keys = show_sequence(&b.keys);
+            match rows.iter_mut().find(|(_, a)| *a == b.action) {
+                Some((shown, _)) => *shown = format!("{shown}, {keys}"),
+                None             => rows.push((keys, b.action)),
+            }
+        }
+        rows
+    }
+
+    // Resolves the keys typed so far against the active contexts
+    pub fn lookup(&self, contexts: &[Context], pressed: &[Key]) -> Lookup {
+        let mut prefix = false;
+        for context in contexts {
+            for b in self.bindings(*context) {
+                if b.keys == pressed {
+                    return Lookup::Action(b.action)
+                }
+                if b.keys.starts_with(pressed) {
+                    prefix = true;
+                }
+            }
+        }
+        match prefix {
+            true  => Lookup::Prefix,
+            false => Lookup::Unbound,
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(s: &str) -> Key {
+        Key::parse(s).unwrap()
+    }
+
+    #[test]
+    fn parses_and_shows_keys() {
+        assert_eq!(key("C-w"), Key::new(KeyCode::Char('w'), KeyModifiers::CONTROL));
+        assert_eq!(key("S-Tab"), key("BackTab"));
+        assert_eq!(key("G"), Key::from(KeyEvent::new(KeyCode::Char('G'), KeyModifiers::SHIFT)));
+        assert_eq!(key("pageup").to_string(), "PageUp");
+        assert_eq!(key("S-Right").to_string(), "S-Right");
+        assert_eq!(key("Space").to_string(), "Space");
+        assert!(Key::parse("Hyper").is_err());
+
+        // Multi-byte keys parse instead of panicking mid-char
+        assert_eq!(key("你"), Key::new(KeyCode::Char('你'), KeyModifiers::NONE));
+        assert_eq!(key("C-é"), Key::new(KeyCode::Char('é'), KeyModifiers::CONTROL));
+        assert_eq!(key("\u{1f600}"), Key::new(KeyCode::Char('\u{1f600}'), KeyModifiers::NONE));
+        assert!(Key::parse("你好").is_err());
+        assert!(Key::parse("C-").is_err());
+    }
+
+    #[test]
+    fn defaults_have_no_conflicts() {
+        let keymap = Keymap::from_config(&toml::Table::new()).unwrap();
+        assert_eq!(keymap.lookup(&[Context::Chat, Context::Main, Context::Global], &[key("q")]), Lookup::Action(Action::Quit));
+    }
+
+    #[test]
+    fn sequences_wait_for_the_next_key() {
+        let keymap = Keymap::default();
+        let chat = [Context::Chat, Context::Main, Context::Global];
+        assert_eq!(keymap.lookup(&chat, &[key("g")]), Lookup::Prefix);
+        assert_eq!(keymap.lookup(&chat, &[key("g"), key("g")]), Lookup::Action(Action::ScrollTop));
+        assert_eq!(keymap.lookup(&chat, &[key("g"), key("x")]), Lookup::Unbound);
+        // Chat bindings aren't active on the rooms list
+        assert_eq!(keymap.lookup(&[Context::Rooms, Context::Main, Context::Global], &[key("g")]), Lookup::Unbound);
+    }
+
+    #[test]
+    fn config_overrides_and_conflicts() {
+        let keys: toml::Table = toml::from_str(r#"
+            global = { quit = "C-q" }
+            chat   = { scroll_top = ["g g", "t"] }
+        "#).unwrap();
+        let keymap = Keymap::from_config(&keys).unwrap();
+        let chat = [Context::Chat, Context::Main, Context::Global];
+        assert_eq!(keymap.lookup(&chat, &[key("C-q")]), Lookup::Action(Action::Quit));
+        assert_eq!(keymap.lookup(&chat, &[key("q")]), Lookup::Unbound);
+        assert_eq!(keymap.lookup(&chat, &[key("t")]), Lookup::Action(Action::ScrollTop));
+
+        // "g" alone would make "g g" unreachable, "p" is already the profile switcher
+        let keys: toml::Table = toml::from_str(r#"
+            chat  = { scroll_bottom = "g" }
+            rooms = { create_room = "p" }
+        "#).unwrap();
+        let problems = Keymap::from_config(&keys).unwrap_err();
+        assert_eq!(problems.len(), 2, "{problems:?}");
+
+        let keys: toml::Table = toml::from_str("chat = { send = \"x\" }\nnope = {}").unwrap();
+        assert_eq!(Keymap::from_config(&keys).unwrap_err().len(), 2);
+    }
+}
@@ -0,0 +1,14 @@
+pub mod config;
+pub mod errors;
+pub mod keymap;
+pub mod profiles;
+
+use std::path::PathBuf;
+
+
+// Everything we keep on disk lives under ~/.config/termochat on Linux
+pub fn config_dir() -> PathBuf {
+    dirs::config_dir()
+        .unwrap_or_else(|| PathBuf::from("."))
+        .join("termochat")
+}
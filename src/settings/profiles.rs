@@ -0,0 +1,186 @@
+use serde::Deserialize;
+use std::{
+    collections::BTreeMap,
+    env, fs,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+use super::{config_dir, errors::SettingsError};
+
+
+// One server plus the account used on it
+#[derive(Clone, Debug, PartialEq)]
+pub struct Profile {
+    pub name:        String,
+    pub host:        String,
+    pub username:    Option<String>, // Pre-filled on Sign In
+    pub preferences: toml::Table,    // Laid over config.toml while the profile is active
+}
+
+// Shape of profiles.toml
+#[derive(Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ProfilesFile {
+    default:  Option<String>,
+    #[serde(default)]
+    profiles: BTreeMap<String, ProfileEntry>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ProfileEntry {
+    host:        String,
+    username:    Option<String>,
+    #[serde(default)]
+    preferences: toml::Table,
+}
+
+#[derive(Clone)]
+pub struct Profiles {
+    pub list:   Vec<Profile>, // Sorted by name
+    pub active: usize,
+}
+
+impl Profile {
+    // Sessions add paths and swap the scheme for ws themselves, so a host is only where the server is
+    fn check_host(&self) -> Result<(), SettingsError> {
+        let rest = self.host.strip_prefix("http://").or_else(|| self.host.strip_prefix("https://"));
+        let problem = match rest.map(|r| r.trim_end_matches('/')) {
+            _ if self.host.is_empty()                    => "has no host",
+            None                                         => "host must start with http:// or https://",
+            Some("")                                     => "has no host",
+            Some(rest) if rest.contains(['/', '?', '#']) => "host can't have a path",
+            Some(_)                                      => return Ok(()),
+        };
+        Err(SettingsError::Invalid(format!("Profile \"{}\" {problem}", self.name)))
+    }
+}
+
+impl Profiles {
+    pub fn default_path() -> PathBuf {
+        config_dir().join("profiles.toml")
+    }
+
+    // Without a profiles file, the configured host (or HOST) becomes the "default" profile
+    pub fn load(path: &Path, wanted: Option<&str>, fallback_host: Option<String>) -> Result<Self, SettingsError> {
+        let file = match fs::read_to_string(path) {
+            Ok(raw) => toml::from_str(&raw)
+                .map_err(|e| SettingsError::Parse { path: path.to_path_buf(), msg: e.to_string() })?,
+            Err(e) if e.kind() == ErrorKind::NotFound => ProfilesFile::from_host(fallback_host),
+            Err(err) => return Err(SettingsError::Io { path: path.to_path_buf(), err }),
+        };
+        Self::from_file(file, wanted)
+    }
+
+    fn from_file(file: ProfilesFile, wanted: Option<&str>) -> Result<Self, SettingsError> {
+        if file.profiles.is_empty() {
+            return Err(SettingsError::Invalid(format!(
+                "No server configured, add a profile to {}, set server.host in config.toml or set HOST",
+                Self::default_path().display(),
+            )))
+        }
+
+        let mut list: Vec<Profile> = file.profiles
+            .into_iter()
+            .map(|(name, entry)| Profile {
+                name,
+                host:        entry.host,
+                username:    entry.username,
+                preferences: entry.preferences,
+            })
+            .collect();
+
+        // Checked as written, stored without trailing slashes
+        for p in &mut list {
+            p.check_host()?;
+            p.host.truncate(p.host.trim_end_matches('/').len());
+        }
+
+        // --profile wins over the file's default
+        let active = match wanted.or(file.default.as_deref()) {
+            Some(name) => list.iter()
+                .position(|p| p.name == name)
+                .ok_or_else(|| SettingsError::Invalid(format!("No profile named \"{name}\"")))?,
+            None       => 0,
+        };
+        Ok(Self { list, active })
+    }
+
+    pub fn current(&self) -> &Profile {
+        &self.list[self.active]
+    }
+}
+
+impl ProfilesFile {
+    fn from_host(host: Option<String>) -> Self {
+        let mut file = Self::default();
+        if let Some(host) = host.or_else(|| env::var("HOST").ok()) {
+            let entry = ProfileEntry { host, username: None, preferences: toml::Table::new() };
+            file.profiles.insert("default".to_string(), entry);
+        }
+        file
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FILE: &str = r#"
+        default = "staging"
+
+        [profiles.production]
+        host = "https://chat.example.com/"
+        username = "ali"
+
+        [profiles.staging]
+        host = "http://localhost:8080"
+
+        [profiles.staging.preferences]
+        theme = "light"
+    "#;
+
+    fn parse(raw: &str, wanted: Option<&str>) -> Result<Profiles, SettingsError> {
+        Profiles::from_file(toml::from_str(raw).unwrap(), wanted)
+    }
+
+    #[test]
+    fn picks_file_default_unless_asked() {
+        let profiles = parse(FILE, None).unwrap();
+        assert_eq!(profiles.current().name, "staging");
+        assert_eq!(profiles.current().preferences["theme"].as_str(), Some("light"));
+
+        let profiles = parse(FILE, Some("production")).unwrap();
+        assert_eq!(profiles.current().host, "https://chat.example.com");
+        assert_eq!(profiles.current().username.as_deref(), Some("ali"));
+    }
+
+    #[test]
+    fn unknown_or_missing_profiles_are_errors() {
+        assert!(matches!(parse(FILE, Some("nope")), Err(SettingsError::Invalid(_))));
+        assert!(matches!(parse("", None), Err(SettingsError::Invalid(_))));
+        assert!(toml::from_str::<ProfilesFile>("[profiles.x]\nhots = \"typo\"").is_err());
+    }
+
+    #[test]
+    fn hosts_are_only_scheme_and_authority() {
+        for (host, problem) in [
+            ("",                              "has no host"),
+            ("https://",                      "has no host"),
+            ("chat.example.com",              "must start with http:// or https://"),
+            ("wss://chat.example.com",        "must start with http:// or https://"),
+            ("https://chat.example.com/api",  "can't have a path"),
+        ] {
+            let raw = format!("[profiles.work]\nhost = \"{host}\"");
+            match parse(&raw, None) {
+                Err(SettingsError::Invalid(msg)) => {
+                    assert!(msg.starts_with("Profile \"work\""), "{msg}");
+                    assert!(msg.contains(problem), "{msg}");
+                },
+                _                                => panic!("{host:?} was accepted"),
+            }
+        }
+        assert!(parse("[profiles.work]\nhost = \"http://127.0.0.1:8080/\"", None).is_ok());
+    }
+}
@@ -0,0 +1,154 @@
+use super::wrap::{char_width, wrap_ranges};
+use unicode_bidi::{BidiInfo, Level};
+
+
+// Terminals draw cells left to right, so Persian/Arabic runs have to be
+// reordered by us (UAX #9) before they hit the buffer. Everything here
+// works on single lines, indices are char indices into the logical text.
+
+fn byte_of(text: &str, index: usize) -> usize {
+    text.char_indices().nth(index).map(|(b, _)| b).unwrap_or(text.len())
+}
+
+// Base direction follows the first strong character
+pub fn is_rtl(text: &str) -> bool {
+    let info = BidiInfo::new(text, None);
+    info.paragraphs.first().is_some_and(|p| p.level.is_rtl())
+}
+
+// Levels of chars [start, end), resolved over the whole text so wrapped rows agree
+fn line_levels(text: &str, start: usize, end: usize) -> Vec<Level> {
+    if start >= end {
+        return Vec::new()
+    }
+    let info = BidiInfo::new(text, None);
+    let Some(para) = info.paragraphs.first() else {
+        return Vec::new()
+    };
+    let levels = info.reordered_levels_per_char(para, byte_of(text, start)..byte_of(text, end));
+    levels[start..end].to_vec()
+}
+
+// Chars [start, end) of text in the order they appear on screen
+pub fn visual_range(text: &str, start: usize, end: usize) -> String {
+    let chars: Vec<char> = text.chars().collect();
+    let levels = line_levels(text, start, end);
+    BidiInfo::reorder_visual(&levels)
+        .into_iter()
+        .map(|i| chars[start + i])
+        .collect()
+}
+
+pub fn visual(text: &str) -> String {
+    visual_range(text, 0, text.chars().count())
+}
+
+// Soft wraps on the logical text first, then lays out every row for the screen
+pub fn wrap_visual(text: &str, width: usize) -> Vec<String> {
+    wrap_ranges(text, width)
+        .into_iter()
+        .map(|(start, end)| visual_range(text, start, end))
+        .collect()
+}
+
+// Screen slot (0 is the left edge) of the caret standing before logical char `index`.
+// Inside RTL runs the caret hugs the right side of its char.
+fn caret_slot(levels: &[Level], order: &[usize], index: usize) -> usize {
+    let n = levels.len();
+    if n == 0 {
+        return 0
+    }
+    let slot_of = |i: usize| order.iter().position(|&l| l == i).unwrap_or(0);
+
+    if index < n {
+        let slot = slot_of(index);
+        if levels[index].is_rtl() { slot + 1 } else { slot }
+    } else {
+        let slot = slot_of(n - 1);
+        if levels[n - 1].is_rtl() { slot } else { slot + 1 }
+    }
+}
+
+// Display column of the caret before logical char `index`, within row [start, end)
+pub fn caret_column(text: &str, start: usize, end: usize, index: usize) -> usize {
+    let chars: Vec<char> = text.chars().collect();
+    let levels = line_levels(text, start, end);
+    let order  = BidiInfo::reorder_visual(&levels);
+    let slot   = caret_slot(&levels, &order, index.clamp(start, end) - start);
+
+    order[..slot].iter().map(|&i| char_width(chars[start + i])).sum()
+}
+
+// Logical caret index one step left or right on screen
+pub fn visual_step(text: &str, index: usize, rightwards: bool) -> usize {
+    let n = text.chars().count();
+    let index  = index.min(n);
+    let levels = line_levels(text, 0, n);
+    let order  = BidiInfo::reorder_visual(&levels);
+    let here   = caret_slot(&levels, &order, index);
+
+    let candidates = (0..=n)
+        .filter(|&i| i != index)
+        .map(|i| (caret_slot(&levels, &order, i), i));
+
+    let next = match rightwards {
+        true  => candidates.filter(|(slot, _)| *slot > here).min_by_key(|(slot, _)| *slot),
+        false => candidates.filter(|(slot, _)| *slot < here).max_by_key(|(slot, _)| *slot),
+    };
+    next.map(|(_, i)| i).unwrap_or(index)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Golden visual forms of mixed Farsi/English lines as a LTR terminal draws them
+    #[test]
+    fn golden_visual_lines() {
+        let cases = [
+            ("hello world",           "hello world"),
+            ("سلام",                  "مالس"),
+            ("hello سلام",            "hello مالس"),
+            ("سلام world",            "world مالس"),
+            ("من Rust دوست دارم",     "مراد تسود Rust نم"),
+            ("نسخه 2.0 آمد",          "دمآ 2.0 هخسن"),
+            ("room اتاق ۱ ready",     "room ۱ قاتا ready"),
+        ];
+        for (logical, expected) in cases {
+            assert_eq!(visual(logical), expected, "visual form of {logical:?}");
+        }
+    }
+
+    #[test]
+    fn detects_base_direction() {
+        assert!(is_rtl("سلام world"));
+        assert!(!is_rtl("hello سلام"));
+        assert!(!is_rtl(""));
+    }
+
+    #[test]
+    fn caret_in_rtl_text_starts_on_the_right() {
+        // Nothing typed yet after "سلام", caret sits at its left end on screen
+        assert_eq!(caret_column("سلام", 0, 4, 0), 4);
+        assert_eq!(caret_column("سلام", 0, 4, 4), 0);
+        assert_eq!(caret_column("abc", 0, 3, 3), 3);
+    }
+
+    #[test]
+    fn arrows_move_visually_in_rtl_text() {
+        // Left arrow walks forward through Farsi
+        assert_eq!(visual_step("سلام", 0, false), 1);
+        assert_eq!(visual_step("سلام", 1, true), 0);
+        assert_eq!(visual_step("abc", 1, true), 2);
+        assert_eq!(visual_step("abc", 0, false), 0);
+    }
+
+    #[test]
+    fn wrapped_rows_keep_paragraph_levels() {
+        let text = "سلام world";
+        // Second row is only the English word, still drawn LTR
+        assert_eq!(visual_range(text, 5, 10), "world");
+        assert_eq!(visual_range(text, 0, 5), " مالس");
+    }
+}
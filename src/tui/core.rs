@@ -1,49 +1,66 @@
-use std::u16;
-
 use crate::components::{
-    app::App,
+    app::{App, ACCOUNT_MENU},
+    logics,
+    forms::InputKind,
+    notices::Severity,
     states,
 };
+use crate::server_talk::{chat::{ChatMessage, ConnState}, session::Visibility};
+use super::{bidi, theme::Theme, wrap};
 
+use chrono::{Local, TimeZone};
 use ratatui::{
-    layout::{Alignment, Constraint, Direction, Flex, Layout, Position},
+    layout::{Alignment, Constraint, Direction, Flex, Layout, Margin, Position, Rect},
     style::{Color, Style, Stylize},
     text::{Line, Span},
-    widgets::{Block, BorderType, Borders, List, ListItem, Paragraph},
+    widgets::{
+        Block, BorderType, Borders, Clear, List, ListItem, Paragraph, Scrollbar, ScrollbarOrientation,
+    },
     Frame,
 };
 
 
-// Custom colors to use
-const BORDER:           Color = Color::Rgb(11, 255, 37);
-const TYPING_BORDER:    Color = Color::Rgb(253, 242, 83);
-const CHAT_FG:          Color = Color::Rgb(203, 3, 8);
-const FORM:             Color = Color::Rgb(247, 155, 35);
-const SELECTED_BOOL:    Color = Color::Rgb(94, 94, 94);
+const SPINNER: [&str; 4] = ["|", "/", "-", "\\"];
+const LOCK: &str = "\u{1f512} "; // In front of private rooms
 
-pub fn draw_ui(f: &mut Frame, app: &App) {
+pub fn draw_ui(f: &mut Frame, app: &App, theme: &Theme) {
     // Will need them at following
     let inputs = &app.form.inputs;
+    let layout = &app.config.layout;
 
     match app.selected_screen {
         states::Screen::Main => {
 
+            // Status bar takes the last row
+            let rows = Layout::default()
+                .direction(Direction::Vertical)
+                .constraints([
+                    Constraint::Min(0),
+                    Constraint::Length(1),
+                ])
+                .split(f.area());
+            f.render_widget(status_bar(app, theme, rows[1].width), rows[1]);
+
             let chunks = Layout::default()
                 .direction(Direction::Horizontal)
                 .constraints([
-                    Constraint::Percentage(30),
-                    Constraint::Percentage(70),
+                    Constraint::Percentage(layout.rooms_width),
+                    Constraint::Percentage(100 - layout.rooms_width),
                 ])
-                .split(f.area());
+                .split(rows[0]);
 
             let room_names: Vec<ListItem> = app.room_names
                 .iter()
                 .enumerate()
                 .map(|(i, m)| {
-                    let content = Line::from(Span::raw(format!("{m}"))
+                    let lock = match app.room_visibility.get(i) {
+                        Some(Visibility::Private) => LOCK,
+                        _                         => "",
+                    };
+                    let content = Line::from(Span::raw(format!("{lock}{}", bidi::visual(m)))
                         .style(
                             if i == app.room_index {
-                                Style::new().fg(CHAT_FG).bg(BORDER)
+                                Style::new().fg(theme.highlight_fg).bg(theme.highlight_bg)
                             } else {
                                 Style::new()
                             }
@@ -57,8 +74,8 @@ pub fn draw_ui(f: &mut Frame, app: &App) {
                 .border_type(BorderType::Rounded)
                 .border_style(Style::default().fg(
                     match app.selected_block {
-                        states::Block::Rooms => BORDER,
-                        _ => Color::White,
+                        states::Block::Rooms => theme.border,
+                        _ => theme.unfocused,
                     }
                 ))
                 .title(Line::from("Rooms").centered());
@@ -66,11 +83,33 @@ pub fn draw_ui(f: &mut Frame, app: &App) {
             let rooms = List::new(room_names).block(rooms.clone());
             f.render_widget(rooms, chunks[0]);
 
+            let main_txt = inputs[app.form.selected_input].borrow();
+            let input_width = chunks[1].width.saturating_sub(2) as usize;
+
+            // Draft lines soft wrapped, cursor tracked as (row, column) among them
+            let mut input_rows: Vec<String> = Vec::new();
+            let mut cursor = (0, 0);
+            for (i, line) in main_txt.iter().enumerate() {
+                let ranges = wrap::wrap_ranges(line, input_width);
+                if i == app.line_index {
+                    let (row, col) = wrap::cursor_in_wrapped(line, app.char_index, input_width);
+                    // Rows may hold RTL runs, so the column comes from their visual order
+                    let col = match ranges.get(row) {
+                        Some((start, end)) => bidi::caret_column(line, *start, *end, app.char_index),
+                        None               => col,
+                    };
+                    cursor = (input_rows.len() + row, col);
+                }
+                input_rows.extend(ranges.iter().map(|(start, end)| bidi::visual_range(line, *start, *end)));
+            }
+            let visible_rows = input_rows.len().max(cursor.0 + 1).min(layout.max_input_rows as usize);
+            let input_top    = (cursor.0 + 1).saturating_sub(visible_rows);
+
             let chat_chunks = Layout::default()
                 .direction(Direction::Vertical)
                 .constraints([
                     Constraint::Min(0),
-                    Constraint::Length(3),
+                    Constraint::Length(visible_rows as u16 + 2),
                 ])
                 .split(chunks[1]);
 
@@ -79,27 +118,40 @@ pub fn draw_ui(f: &mut Frame, app: &App) {
                 .border_type(BorderType::Rounded)
                 .border_style(Style::default().fg(
                     match app.selected_block {
-                        states::Block::Chat => BORDER,
-                        _ => Color::White,
+                        states::Block::Chat => theme.border,
+                        _ => theme.unfocused,
                     }
                 ))
-                .title(Line::from("Messages").centered());
+                .title(Line::from(format!("Messages [{}]", app.conn_state)).centered());
 
-            let messages: Vec<ListItem> = app.messages
-                .iter()
-                .map(|m| {
-                    let content = Line::from(Span::raw(format!("{m}"))
-                        .style(
-                            match app.is_user_msg {
-                                true => Style::new().fg(CHAT_FG).bg(BORDER),
-                                false => Style::new().bg(Color::Gray).fg(Color::Black)
-                            }
-                        ));
-                    ListItem::new(content)
-                })
-                .collect();
-            let chat = List::new(messages).block(chat.clone());
-            f.render_widget(chat, chat_chunks[0]);
+            let inner  = chat.inner(chat_chunks[0]);
+            // Last column is taken by the scrollbar
+            app.chat_width.set(inner.width.saturating_sub(1));
+            app.chat_height.set(inner.height);
+            let lines  = chat_lines(app, theme);
+            let height = inner.height;
+
+            // chat_scroll_index counts lines up from the bottom
+            let max_top = lines.len().saturating_sub(height as usize);
+            let top     = max_top.saturating_sub(app.chat_scroll_index);
+
+            let chat = match app.unread {
+                0 => chat,
+                n => chat.title_bottom(Line::from(format!(" {n} new messages ")).centered().bold()),
+            };
+            let chat_para = Paragraph::new(lines)
+                .block(chat)
+                .scroll((top as u16, 0));
+            f.render_widget(chat_para, chat_chunks[0]);
+
+            let mut scroll_state = app.chat_scroll_state
+                .content_length(max_top)
+                .position(top);
+            f.render_stateful_widget(
+                Scrollbar::new(ScrollbarOrientation::VerticalRight),
+                chat_chunks[0].inner(Margin { vertical: 1, horizontal: 0 }),
+                &mut scroll_state,
+            );
 
             let typing_blk = Block::default()
                 .borders(Borders::ALL)
@@ -108,42 +160,37 @@ pub fn draw_ui(f: &mut Frame, app: &App) {
                     match app.selected_block {
                         states::Block::Typing => {
                             match app.mode {
-                                states::Modes::Normal => BORDER,
-                                states::Modes::Insert => TYPING_BORDER,
+                                states::Modes::Normal => theme.border,
+                                states::Modes::Insert => theme.typing_border,
                             }
                         },
-                        _ => Color::White,
+                        _ => theme.unfocused,
                     }
                 ));
 
-            let main_txt = inputs[app.form.selected_input].borrow();
             // What to show on typing box
-            let showing_text = match app.mode {
-                states::Modes::Normal => {
-                    match main_txt[app.line_index].as_str() {
-                        // Keep the draft message
-                        "" => {vec![Line::from("Type here ...").style(Style::new().dark_gray())]}
-                        _ => {vec![Line::from(main_txt[app.line_index].as_str()).style(Style::new().fg(CHAT_FG))]}
-                    }
-                },
-                states::Modes::Insert => vec![Line::from(main_txt[app.line_index].as_str()).style(CHAT_FG)]
+            let is_empty = main_txt.iter().all(|l| l.is_empty());
+            let showing_text = match (&app.mode, is_empty) {
+                // Keep the draft message
+                (states::Modes::Normal, true) => vec![Line::from("Type here ...").style(Style::new().fg(theme.muted))],
+                _ => input_rows.iter()
+                    .map(|r| Line::from(r.as_str()).style(Style::new().fg(theme.text)))
+                    .collect(),
             };
             let typing_para = Paragraph::new(
                     showing_text
                 )
-                .block(typing_blk);
+                .block(typing_blk)
+                .scroll((input_top as u16, 0));
             f.render_widget(typing_para, chat_chunks[1]);
 
             // To set a cursor on typing box
-            match app.mode {
-                states::Modes::Insert => {
-                    #[allow(clippy::cast_possible_truncation)]
-                    f.set_cursor_position(Position::new(
-                        chat_chunks[1].x + app.char_index as u16 + 1,
-                        chat_chunks[1].y + 1
-                    ));
-                },
-                _ => {}
+            if let states::Modes::Insert = app.mode {
+                #[allow(clippy::cast_possible_truncation)]
+                f.set_cursor_position(Position::new(
+                    chat_chunks[1].x + cursor.1 as u16 + 1,
+                    chat_chunks[1].y + (cursor.0 - input_top) as u16 + 1
+                ));
             }
 
         },
@@ -170,7 +217,7 @@ pub fn draw_ui(f: &mut Frame, app: &App) {
             let form_blk = Block::default()
                 .borders(Borders::ALL)
                 .border_type(BorderType::Rounded)
-                .border_style(FORM)
+                .border_style(theme.form)
                 .title(Line::from("Choose Form").centered());
             f.render_widget(form_blk, hchunk[0]);
 
@@ -184,13 +231,11 @@ pub fn draw_ui(f: &mut Frame, app: &App) {
                 .split(hchunk[0]);
 
             for (i, title) in titles.clone().iter().enumerate() {
-                let border: Borders;
                 let is_selected = app.form.selected_input == i;
-                if is_selected {
-                    border = Borders::ALL;
-                } else {
-                    border = Borders::NONE;
-                }
+                let border = match is_selected {
+                    true  => Borders::ALL,
+                    false => Borders::NONE,
+                };
 
                 let row_block = Block::default()
                     .borders(border)
@@ -208,13 +253,13 @@ pub fn draw_ui(f: &mut Frame, app: &App) {
                 f.render_widget(title_line, rows[i]);
             }
         },
-        _ => {
+        states::Screen::Profiles => {
 
             // To draw center layout ----------
             let vchunk = Layout::default()
                 .direction(Direction::Vertical)
                 .constraints([
-                    Constraint::Percentage(60),
+                    Constraint::Length(app.profiles.list.len() as u16 + 2),
                 ])
                 .flex(Flex::Center)
                 .split(f.area());
@@ -222,114 +267,829 @@ pub fn draw_ui(f: &mut Frame, app: &App) {
             let hchunk = Layout::default()
                 .direction(Direction::Horizontal)
                 .constraints([
-                    Constraint::Percentage(40),
+                    Constraint::Percentage(50),
                 ])
                 .flex(Flex::Center)
                 .split(vchunk[0]);
             // ---------------------------------
 
-            let form_blk = Block::default()
+            let profiles_blk = Block::default()
                 .borders(Borders::ALL)
                 .border_type(BorderType::Rounded)
-                .border_style(FORM)
-                .title(Line::from(
-                    match app.form.kind {
-                        states::Forms::SignUp      => "Sign Up",
-                        states::Forms::SignIn      => "Sign In",
-                        states::Forms::RoomCreator => "Room Creation",
-                        states::Forms::RoomEdit    => "Room Edit",
-                        _                          => "", // Not happening
+                .border_style(theme.form)
+                .title(Line::from("Profiles").centered())
+                .title_bottom(Line::from(" Enter switch, Esc back ").centered());
+
+            // Active profile is marked, hovered one is highlighted
+            let items: Vec<ListItem> = app.profiles.list
+                .iter()
+                .enumerate()
+                .map(|(i, p)| {
+                    let marker = if i == app.profiles.active { "*" } else { " " };
+                    let line = Line::from(vec![
+                        Span::raw(format!("{marker} {}", p.name)).bold(),
+                        Span::raw(format!("  {}", p.host)),
+                    ]);
+                    match i == app.profile_hover {
+                        true  => ListItem::new(line.style(Style::new().fg(theme.highlight_fg).bg(theme.highlight_bg))),
+                        false => ListItem::new(line),
                     }
-                ).centered());
-            f.render_widget(form_blk, hchunk[0]);
+                })
+                .collect();
+
+            f.render_widget(List::new(items).block(profiles_blk), hchunk[0]);
+        },
+        states::Screen::Account => {
 
-            let cols = Layout::default()
+            // To draw center layout ----------
+            let vchunk = Layout::default()
+                .direction(Direction::Vertical)
+                .constraints([
+                    Constraint::Length(ACCOUNT_MENU.len() as u16 + 2),
+                ])
+                .flex(Flex::Center)
+                .split(f.area());
+
+            let hchunk = Layout::default()
                 .direction(Direction::Horizontal)
                 .constraints([
-                    Constraint::Percentage(30),
-                    Constraint::Percentage(70),
+                    Constraint::Percentage(40),
                 ])
-                .margin(1)
-                .split(hchunk[0]);
+                .flex(Flex::Center)
+                .split(vchunk[0]);
+            // ---------------------------------
 
-            // To draw inner layout ------------
-            let titles: Vec<String> = match app.form.kind {
-                states::Forms::SignUp      => vec![format!("Username:"),
-                                              format!(""), format!("Password:"),
-                                              format!(""), format!("Question:"),
-                                              format!(""), format!("Answer:")],
-                states::Forms::SignIn      => vec![format!("Username:"), format!(""),
-                                              format!("Password:")],
-                states::Forms::RoomCreator |
-                states::Forms::RoomEdit    => vec![format!("Roomname:"), format!(""),
-                                                   format!("IsPublic:")],
-                _                          => vec![format!("")], // Not happening
-            };
+            let who = format!(" {} @ {} ", app.session.show_name.as_deref().unwrap_or("-"), app.session.host);
+            let account_blk = Block::default()
+                .borders(Borders::ALL)
+                .border_type(BorderType::Rounded)
+                .border_style(theme.form)
+                .title(Line::from("Account").centered())
+                .title_bottom(Line::from(who).centered());
 
-            let rows: Vec<Constraint> = vec![Constraint::Percentage(100 / titles.len() as u16); titles.len()];
-            let rows = Layout::default()
-                .direction(Direction::Vertical)
-                .constraints(rows);
+            let items: Vec<ListItem> = ACCOUNT_MENU
+                .iter()
+                .enumerate()
+                .map(|(i, entry)| {
+                    let line = Line::from(format!(" {entry}"));
+                    match i == app.account_hover {
+                        true  => ListItem::new(line.style(Style::new().fg(theme.highlight_fg).bg(theme.highlight_bg))),
+                        false => ListItem::new(line),
+                    }
+                })
+                .collect();
 
-            for (i, title) in titles.clone().iter().enumerate() {
-                if i % 2 == 0 {
-                    let border: Borders;
-                    let is_selected = i == app.form.selected_input * 2;
-                    if is_selected {
-                        border = Borders::ALL;
-                        match app.mode {
-                            states::Modes::Insert => {
-                                f.set_cursor_position(Position::new(
-                                    rows.clone().split(cols[1])[i].x + app.char_index as u16 + 1,
-                                    rows.clone().split(cols[1])[i].y + 1,
-                                ));
-                            },
-                            _                     => {}
-                        };
-                    } else {
-                        border = Borders::NONE;
-                    };
+            f.render_widget(List::new(items).block(account_blk), hchunk[0]);
+        },
+        states::Screen::Form => draw_form(f, app, theme),
+    }
 
-                    let row_block = Block::default()
-                        .borders(border)
-                        .border_type(BorderType::Rounded);
-
-                    if *title == format!("IsPublic:") {
-                        // This block decides how IsPublic input is gotten
-                        let stl: Style;
-                        if is_selected {
-                            stl = Style::new().bg(SELECTED_BOOL);
-                        } else {
-                            stl = Style::new();
-                        }
-
-                        let dyn_bool = Paragraph::new(
-                            vec![Line::from(Span::from(app.form.is_public.to_string())).style(stl.fg(CHAT_FG))]
-                        ).block(row_block.clone().borders(Borders::NONE));
-                        f.render_widget(dyn_bool, rows.clone().split(cols[1])[i]);
-                    } else {
-                        let main_txt = inputs[i/2].borrow();
-                        let input_para = Paragraph::new(
-                            vec![Line::from(main_txt[app.line_index].as_str()).style(Style::new().fg(CHAT_FG))]
-                        )
-                            .block(row_block.clone());
-                        f.render_widget(input_para, rows.clone().split(cols[1])[i]);
-                    }
+    let area = f.area();
+    let bottom_row = Rect::new(area.x, area.bottom().saturating_sub(1), area.width, area.height.min(1));
+
+    // Main has the status bar for these, other screens get them over the bottom row
+    if !matches!(app.selected_screen, states::Screen::Main) {
+        if let Some(notice) = notice_span(app, theme) {
+            f.render_widget(Paragraph::new(Line::from(notice)), bottom_row);
+        }
+        if let Some(loading) = loading_span(app, theme) {
+            f.render_widget(Paragraph::new(Line::from(loading)).alignment(Alignment::Right), bottom_row);
+        }
+    }
+
+    draw_toasts(f, app, theme);
+
+    if let Some(confirm) = &app.confirm {
+        draw_confirm(f, confirm, theme);
+    }
+
+    // Help goes over everything else
+    if app.show_help {
+        draw_help(f, app, theme);
+    }
+}
+
+// Any form, laid out from its spec: label on the left, input and its error on the right
+fn draw_form(f: &mut Frame, app: &App, theme: &Theme) {
+    let form = &app.form;
+    let field_rows = form.spec.fields.len() as u16 * 4; // Bordered input plus an error row
+
+    // To draw center layout ----------
+    let vchunk = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([
+            Constraint::Length(field_rows + 2),
+        ])
+        .flex(Flex::Center)
+        .split(f.area());
 
-                    let title_line = Paragraph::new(
-                        vec![
-                            Line::from(Span::from("")), // To align title vertically
-                            Line::from(Span::from(title))
-                        ]
-                    )
-                        .alignment(Alignment::Center)
-                        .block(row_block.clone().borders(Borders::NONE));
+    let hchunk = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints([
+            Constraint::Percentage(40),
+        ])
+        .flex(Flex::Center)
+        .split(vchunk[0]);
+    // ---------------------------------
 
-                    f.render_widget(title_line, rows.clone().split(cols[0])[i]);
+    let form_blk = Block::default()
+        .borders(Borders::ALL)
+        .border_type(BorderType::Rounded)
+        .border_style(theme.form)
+        .title(Line::from(form.spec.title).centered());
+    let form_blk = match &form.note {
+        Some(note) => form_blk.title_bottom(Line::from(format!(" {} ", bidi::visual(note))).centered().fg(theme.text)),
+        None       => form_blk,
+    };
+    f.render_widget(form_blk, hchunk[0]);
+
+    let cols = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints([
+            Constraint::Percentage(30),
+            Constraint::Percentage(70),
+        ])
+        .margin(1)
+        .split(hchunk[0]);
+
+    let rows = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints(form.spec.fields.iter().flat_map(|_| [Constraint::Length(3), Constraint::Length(1)]));
+    let labels = rows.clone().split(cols[0]);
+    let values = rows.split(cols[1]);
+
+    let revealed = form.revealed_until.is_some_and(|until| until > app.ticks);
+    for (i, field) in form.spec.fields.iter().enumerate() {
+        let is_selected = i == form.selected_input;
+        let area = values[i * 2];
+        let row_block = Block::default()
+            .borders(if is_selected { Borders::ALL } else { Borders::NONE })
+            .border_type(BorderType::Rounded);
+
+        match &field.kind {
+            // Picked rather than typed
+            InputKind::Boolean | InputKind::Choice(_) => {
+                let stl = match is_selected {
+                    true  => Style::new().bg(theme.selected_bool),
+                    false => Style::new(),
+                };
+                let shown = match field.fixed {
+                    true  => Line::from(format!("{} (fixed)", form.value(i))).style(stl.fg(theme.muted)),
+                    false => Line::from(form.value(i)).style(stl.fg(theme.text)),
+                };
+                // Lined up with the text of typed fields
+                f.render_widget(Paragraph::new(vec![Line::from(""), shown]), area.inner(Margin { vertical: 0, horizontal: 1 }));
+            },
+            InputKind::Text | InputKind::Secret => {
+                let main_txt = form.inputs[i].borrow();
+                let text     = main_txt[0].as_str();
+                let width    = area.width.saturating_sub(2) as usize;
+                let masked   = field.kind == InputKind::Secret && !revealed;
+
+                // Long input scrolls sideways to keep the cursor in view
+                let visual = match masked {
+                    true  => "\u{2022}".repeat(text.chars().count()),
+                    false => bidi::visual(text),
+                };
+                let caret = match (is_selected, masked) {
+                    (false, _)    => 0,
+                    (true, true)  => app.char_index, // Bullets have no direction
+                    (true, false) => bidi::caret_column(text, 0, text.chars().count(), app.char_index),
+                };
+                let (shown, hidden) = wrap::skip_columns(&visual, caret.saturating_sub(width.saturating_sub(1)));
+
+                if is_selected && matches!(app.mode, states::Modes::Insert) {
+                    f.set_cursor_position(Position::new(
+                        area.x + caret.saturating_sub(hidden) as u16 + 1,
+                        area.y + 1,
+                    ));
                 }
+
+                let typing = is_selected && matches!(app.mode, states::Modes::Insert);
+                let line = match (text.is_empty() && !typing, field.placeholder) {
+                    (true, hint) if !hint.is_empty() => Line::from(hint).style(Style::new().fg(theme.muted)),
+                    _                                => Line::from(shown).style(Style::new().fg(theme.text)),
+                };
+                f.render_widget(Paragraph::new(vec![line]).block(row_block), area);
+            },
+        }
+
+        // Fields that aren't in play yet are dimmed
+        let label_style = match i < form.active {
+            true  => Style::new(),
+            false => Style::new().fg(theme.muted),
+        };
+        let label = Paragraph::new(vec![
+                Line::from(""), // To align label with the input text
+                Line::from(field.label),
+            ])
+            .alignment(Alignment::Center)
+            .style(label_style);
+        f.render_widget(label, labels[i * 2]);
+
+        if let Some(err) = form.shown_error(i) {
+            f.render_widget(Paragraph::new(Line::from(format!(" {err}")).fg(theme.error)), values[i * 2 + 1]);
+        }
+    }
+}
+
+fn severity_color(severity: Severity, theme: &Theme) -> Color {
+    match severity {
+        Severity::Info    => theme.form,
+        Severity::Success => theme.success,
+        Severity::Warning => theme.warning,
+        Severity::Error   => theme.error,
+    }
+}
+
+// Latest notice still up
+fn notice_span<'a>(app: &'a App, theme: &Theme) -> Option<Span<'a>> {
+    app.notices.latest()
+        .map(|n| Span::raw(n.text.as_str()).fg(severity_color(n.severity, theme)))
+}
+
+// Warnings and errors stacked on the top right corner, newest at the bottom
+fn draw_toasts(f: &mut Frame, app: &App, theme: &Theme) {
+    let area  = f.area();
+    let width = (area.width / 3).max(30).min(area.width);
+    let inner = width.saturating_sub(2) as usize;
+    let mut y = area.y;
+
+    for notice in app.notices.toasts() {
+        let rows   = bidi::wrap_visual(&notice.text, inner);
+        let height = (rows.len() as u16 + 2).min(area.bottom().saturating_sub(y));
+        if height < 3 {
+            break
+        }
+        let rect  = Rect::new(area.right() - width, y, width, height);
+        let color = severity_color(notice.severity, theme);
+        let title = match notice.severity {
+            Severity::Error => "Error",
+            _               => "Warning",
+        };
+        let toast = Paragraph::new(rows.into_iter().map(Line::from).collect::<Vec<_>>())
+            .style(Style::new().fg(theme.text))
+            .block(Block::default()
+                .borders(Borders::ALL)
+                .border_type(BorderType::Rounded)
+                .border_style(color)
+                .title(Line::from(title).fg(color)));
+
+        f.render_widget(Clear, rect);
+        f.render_widget(toast, rect);
+        y += height;
+    }
+}
+
+// Session calls still running in background
+fn loading_span(app: &App, theme: &Theme) -> Option<Span<'static>> {
+    match app.pending {
+        0 => None,
+        _ => {
+            let spinner = SPINNER[app.ticks % SPINNER.len()];
+            Some(Span::raw(format!("{spinner} {} ...", app.loading)).fg(theme.form))
+        },
+    }
+}
+
+// Mode, who we are, where we are and how the connection is doing, notices on the right
+fn status_bar<'a>(app: &'a App, theme: &Theme, width: u16) -> Paragraph<'a> {
+    let mode = match app.mode {
+        states::Modes::Normal => Span::raw(" NORMAL ").fg(theme.highlight_fg).bg(theme.highlight_bg),
+        states::Modes::Insert => Span::raw(" INSERT ").fg(theme.highlight_fg).bg(theme.typing_border),
+    };
+    let room = match (&app.current_room, app.current_room_name()) {
+        (Some(hash), Some(name)) => format!("#{} ({})", bidi::visual(name), short_hash(hash)),
+        (Some(hash), None)       => format!("({})", short_hash(hash)),
+        (None, _)                => "no room".to_string(),
+    };
+    let conn = match app.conn_state {
+        ConnState::Connected => Span::raw(app.conn_state.to_string()).fg(theme.form),
+        ConnState::Offline   => Span::raw(app.conn_state.to_string()).fg(theme.muted),
+        _                    => Span::raw(app.conn_state.to_string()).fg(theme.error),
+    };
+    let sep = || Span::raw(" | ").fg(theme.muted);
+
+    let mut spans = vec![
+        mode,
+        Span::raw(" "),
+        Span::raw(app.session.show_name.clone().unwrap_or_else(|| "-".to_string())).bold(),
+        sep(),
+        Span::raw(room),
+        sep(),
+        conn,
+        sep(),
+        Span::raw(app.session.host.as_str()).fg(theme.muted),
+    ];
+
+    // Notices are pushed to the right end of the row
+    let right: Vec<Span> = [
+        notice_span(app, theme),
+        loading_span(app, theme),
+    ].into_iter().flatten().collect();
+    let used: usize = spans.iter().chain(right.iter()).map(Span::width).sum::<usize>() + right.len();
+    spans.push(Span::raw(" ".repeat((width as usize).saturating_sub(used))));
+    for span in right {
+        spans.push(Span::raw(" "));
+        spans.push(span);
+    }
+
+    Paragraph::new(Line::from(spans))
+}
+
+fn short_hash(hash: &str) -> &str {
+    hash.get(..8).unwrap_or(hash)
+}
+
+// Yes / no question in the middle of the screen
+fn draw_confirm(f: &mut Frame, confirm: &states::Confirm, theme: &Theme) {
+    let question = match confirm {
+        states::Confirm::CloseRoom { name, .. } => format!("Close room \"{}\" for everyone?", bidi::visual(name)),
+        states::Confirm::Logout                 => "Log out of this account?".to_string(),
+    };
+
+    // To draw center layout ----------
+    let vchunk = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([
+            Constraint::Length(3),
+        ])
+        .flex(Flex::Center)
+        .split(f.area());
+
+    let hchunk = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints([
+            Constraint::Length(question.chars().count() as u16 + 4),
+        ])
+        .flex(Flex::Center)
+        .split(vchunk[0]);
+    // ---------------------------------
+
+    let confirm_blk = Block::default()
+        .borders(Borders::ALL)
+        .border_type(BorderType::Rounded)
+        .border_style(theme.error)
+        .title_bottom(Line::from(" y yes, any other key no ").centered());
+
+    f.render_widget(Clear, hchunk[0]);
+    f.render_widget(Paragraph::new(question).alignment(Alignment::Center).fg(theme.text).block(confirm_blk), hchunk[0]);
+}
+
+// Keys of every active keymap section, straight from the keymap
+fn draw_help(f: &mut Frame, app: &App, theme: &Theme) {
+    let mut lines: Vec<Line> = Vec::new();
+    for context in logics::active_contexts(app) {
+        let rows = app.config.keymap.help(context);
+        if rows.is_empty() {
+            continue
+        }
+        if !lines.is_empty() {
+            lines.push(Line::from(""));
+        }
+        lines.push(Line::from(context.title()).bold().fg(theme.form));
+
+        let keys_width = rows.iter().map(|(keys, _)| keys.chars().count()).max().unwrap_or(0);
+        for (keys, action) in rows {
+            lines.push(Line::from(vec![
+                Span::raw(format!("  {keys:<keys_width$}  ")).bold(),
+                Span::raw(action.describe()),
+            ]));
+        }
+    }
+
+    // To draw center layout ----------
+    let vchunk = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([
+            Constraint::Length(lines.len() as u16 + 2),
+        ])
+        .flex(Flex::Center)
+        .split(f.area());
+
+    let hchunk = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints([
+            Constraint::Percentage(60),
+        ])
+        .flex(Flex::Center)
+        .split(vchunk[0]);
+    // ---------------------------------
+
+    let help_blk = Block::default()
+        .borders(Borders::ALL)
+        .border_type(BorderType::Rounded)
+        .border_style(theme.form)
+        .title(Line::from("Keys").centered())
+        .title_bottom(Line::from(" Any key closes ").centered());
+
+    f.render_widget(Clear, hchunk[0]);
+    f.render_widget(Paragraph::new(lines).style(Style::new().fg(theme.text)).block(help_blk), hchunk[0]);
+}
+
+// Every line of the chat pane, oldest first
+// Wrapped and styled rows of each message body, kept across frames.
+// Rebuilt when the width or the theme changes, or when a message isn't the one cached
+#[derive(Default)]
+pub struct ChatRows {
+    width:   u16,
+    theme:   Option<Theme>,
+    entries: Vec<(String, bool, Vec<Line<'static>>)>, // Message id, own, rows
+}
+
+impl ChatRows {
+    // Brings the cache in line with app's messages
+    fn refresh(&mut self, app: &App, theme: &Theme) {
+        let width = app.chat_width.get();
+        if self.width != width || self.theme.as_ref() != Some(theme) {
+            self.width   = width;
+            self.theme   = Some(theme.clone());
+            self.entries.clear();
+        }
+
+        let kept = self.entries.iter()
+            .zip(&app.messages)
+            .take_while(|((id, own, _), m)| *id == m.id && *own == app.is_own(m))
+            .count();
+        self.entries.truncate(kept);
+        for m in &app.messages[kept..] {
+            let own = app.is_own(m);
+            self.entries.push((m.id.clone(), own, body_rows(m, own, width as usize, theme)));
+        }
+    }
+}
+
+fn body_rows(m: &ChatMessage, own: bool, width: usize, theme: &Theme) -> Vec<Line<'static>> {
+    let (style, alignment) = message_look(own, theme);
+    m.lines()
+        .flat_map(|l| bidi::wrap_visual(l, width))
+        .map(|l| Line::from(Span::raw(l).style(style)).alignment(alignment))
+        .collect()
+}
+
+fn message_look(own: bool, theme: &Theme) -> (Style, Alignment) {
+    match own {
+        true  => (Style::new().fg(theme.own_fg).bg(theme.own_bg), Alignment::Right),
+        false => (Style::new().fg(theme.other_fg).bg(theme.other_bg), Alignment::Left),
+    }
+}
+
+pub fn chat_lines<'a>(app: &'a App, theme: &Theme) -> Vec<Line<'a>> {
+    let stamps = &app.config.timestamps;
+    let mut rows = app.chat_rows.borrow_mut();
+    rows.refresh(app, theme);
+
+    let mut lines = Vec::new();
+    for (i, (m, (_, own, body))) in app.messages.iter().zip(&rows.entries).enumerate() {
+        let (_, alignment) = message_look(*own, theme);
+
+        // Consecutive messages of one author share a header and spacing
+        let starts_group = i == 0 || app.messages[i - 1].sender != m.sender;
+        let ends_group   = app.messages.get(i + 1).is_none_or(|next| next.sender != m.sender);
+
+        if starts_group {
+            let sender = match (own, m.sender.as_str()) {
+                (true, _)  => "You",
+                (_, "")    => "Unknown",
+                (_, name)  => name,
+            };
+            let mut header = vec![Span::raw(format!("{}:", bidi::visual(sender))).bold()];
+            if stamps.show {
+                header.push(Span::raw(format!(" {}", local_time(m.timestamp, &stamps.format))).fg(theme.muted));
             }
-            // ---------------------------------
+            lines.push(Line::from(header).alignment(alignment));
+        }
+        lines.extend(body.iter().cloned());
+        if ends_group {
+            lines.push(Line::from(""));
+        }
+    }
+    lines
+}
 
+// Lines the last message added to chat_lines, counting the header and spacing it brought
+pub fn last_message_lines(app: &App, theme: &Theme) -> usize {
+    let mut rows = app.chat_rows.borrow_mut();
+    rows.refresh(app, theme);
+    let body = rows.entries.last().map_or(0, |(_, _, body)| body.len());
+    let n = app.messages.len();
+    match n >= 2 && app.messages[n - 2].sender == app.messages[n - 1].sender {
+        true  => body,     // Joins the group above, its spacing just moves down
+        false => body + 2, // Header and spacing of its own
+    }
+}
+
+// Message timestamps are unix millis, shown in the user's timezone
+fn local_time(millis: u64, format: &str) -> String {
+    Local.timestamp_millis_opt(millis as i64)
+        .single()
+        .map(|t| t.format(format).to_string())
+        .unwrap_or_default()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        components::{
+            app::tests::{buffer_text, offline_app},
+            forms::Form,
+        },
+    };
+    use ratatui::{backend::TestBackend, Terminal};
+    use tokio::runtime::Runtime;
+
+    fn row_text(terminal: &Terminal<TestBackend>, y: u16) -> String {
+        let buffer = terminal.backend().buffer();
+        (0..buffer.area.width).map(|x| buffer[(x, y)].symbol()).collect()
+    }
+
+    #[test]
+    fn long_messages_wrap_instead_of_clipping() {
+        let runtime = Runtime::new().unwrap();
+        let (mut app, _ws) = offline_app(&runtime);
+        let mut terminal = Terminal::new(TestBackend::new(60, 20)).unwrap();
+
+        let body = "alpha bravo charlie delta echo foxtrot golf hotel india juliet".to_string();
+        let mut msg = ChatMessage::new("room", "someone", body);
+        msg.id = "1".to_string();
+        app.messages.push(msg);
+
+        terminal.draw(|f| draw_ui(f, &app, &app.theme)).unwrap();
+        let text = buffer_text(&terminal);
+        assert!(text.contains("alpha bravo"));
+        assert!(text.contains("juliet"));
+
+        // Both ends of the body sit on separate rows
+        let alpha_row  = (0..20).find(|y| row_text(&terminal, *y).contains("alpha")).unwrap();
+        let juliet_row = (0..20).find(|y| row_text(&terminal, *y).contains("juliet")).unwrap();
+        assert!(juliet_row > alpha_row);
+    }
+
+    // Same sender throughout, so each message takes a single row after the first
+    fn fill_chat(app: &mut App, from: usize, to: usize) {
+        app.current_room = Some("room".to_string());
+        for n in from..to {
+            app.receive_message(ChatMessage::new("room", "bo", format!("msg {n}")));
+        }
+    }
+
+    // Rows of the scrollbar's thumb, between its arrows on the right edge of the chat
+    fn thumb_rows(terminal: &Terminal<TestBackend>) -> Vec<u16> {
+        let buffer = terminal.backend().buffer();
+        let area   = buffer.area;
+        let column = (0..area.width)
+            .find(|x| (0..area.height).any(|y| buffer[(*x, y)].symbol() == "\u{25b2}"))
+            .unwrap();
+        (0..area.height).filter(|y| buffer[(column, *y)].symbol() == "\u{2588}").collect()
+    }
+
+    #[test]
+    fn chat_follows_only_at_bottom_and_counts_new_messages() {
+        let runtime = Runtime::new().unwrap();
+        let (mut app, _ws) = offline_app(&runtime);
+        let mut terminal = Terminal::new(TestBackend::new(60, 20)).unwrap();
+        terminal.draw(|f| draw_ui(f, &app, &app.theme)).unwrap();
+
+        fill_chat(&mut app, 0, 30);
+        terminal.draw(|f| draw_ui(f, &app, &app.theme)).unwrap();
+        assert!(buffer_text(&terminal).contains("msg 29"));
+        assert_eq!(app.chat_scroll_index, 0);
+
+        // Scrolled up, the view stays put and arrivals are counted
+        app.scroll_chat_up(5);
+        terminal.draw(|f| draw_ui(f, &app, &app.theme)).unwrap();
+        let first_row = row_text(&terminal, 1);
+        fill_chat(&mut app, 30, 32);
+        assert_eq!(app.chat_scroll_index, 7);
+        assert_eq!(app.unread, 2);
+        terminal.draw(|f| draw_ui(f, &app, &app.theme)).unwrap();
+        let text = buffer_text(&terminal);
+        assert!(text.contains(" 2 new messages "));
+        assert!(!text.contains("msg 31"));
+        assert_eq!(row_text(&terminal, 1), first_row);
+
+        // Back at the bottom, the marker goes and new messages are followed again
+        app.scroll_chat_bottom();
+        assert_eq!(app.unread, 0);
+        fill_chat(&mut app, 32, 33);
+        assert_eq!(app.chat_scroll_index, 0);
+        terminal.draw(|f| draw_ui(f, &app, &app.theme)).unwrap();
+        let text = buffer_text(&terminal);
+        assert!(text.contains("msg 32"));
+        assert!(!text.contains("new messages"));
+    }
+
+    #[test]
+    fn scrollbar_thumb_follows_the_view() {
+        let runtime = Runtime::new().unwrap();
+        let (mut app, _ws) = offline_app(&runtime);
+        let mut terminal = Terminal::new(TestBackend::new(60, 20)).unwrap();
+        terminal.draw(|f| draw_ui(f, &app, &app.theme)).unwrap();
+        fill_chat(&mut app, 0, 100);
+
+        let thumb_at = |app: &App, terminal: &mut Terminal<TestBackend>| {
+            terminal.draw(|f| draw_ui(f, app, &app.theme)).unwrap();
+            thumb_rows(terminal)
+        };
+
+        let bottom = thumb_at(&app, &mut terminal);
+        app.scroll_chat_top();
+        let top = thumb_at(&app, &mut terminal);
+        // Oldest message puts the thumb right under the up arrow
+        assert!(row_text(&terminal, top[0] - 1).contains('\u{25b2}'));
+        app.scroll_chat_down(40);
+        let middle = thumb_at(&app, &mut terminal);
+
+        assert!(!top.is_empty() && !middle.is_empty() && !bottom.is_empty());
+        assert!(top[0] < middle[0] && middle[0] < bottom[0]);
+    }
+
+    #[test]
+    fn wrapped_rows_are_rebuilt_for_a_new_width_or_theme() {
+        let runtime = Runtime::new().unwrap();
+        let (mut app, _ws) = offline_app(&runtime);
+        app.current_room = Some("room".to_string());
+        app.receive_message(ChatMessage::new("room", "bo", "alpha bravo charlie delta".to_string()));
+
+        app.chat_width.set(40);
+        assert_eq!(chat_lines(&app, &app.theme).len(), 3);
+        app.chat_width.set(12);
+        assert_eq!(chat_lines(&app, &app.theme).len(), 5);
+
+        let light = Theme::by_name("light").unwrap();
+        let body  = chat_lines(&app, &light)[1].spans[0].style;
+        assert_eq!(body.bg, Some(light.other_bg));
+    }
+
+    #[test]
+    fn own_messages_stand_apart_and_groups_share_a_header() {
+        let runtime = Runtime::new().unwrap();
+        let (mut app, _ws) = offline_app(&runtime);
+        let mut terminal = Terminal::new(TestBackend::new(60, 20)).unwrap();
+        app.session.show_name = Some("ali".to_string());
+        app.current_room = Some("room".to_string());
+        for (sender, body) in [("bo", "first"), ("bo", "second"), ("ali", "mine"), ("bo", "third")] {
+            app.receive_message(ChatMessage::new("room", sender, body.to_string()));
+        }
+
+        terminal.draw(|f| draw_ui(f, &app, &app.theme)).unwrap();
+        let text = buffer_text(&terminal);
+        assert_eq!(text.matches("bo:").count(), 2);
+        assert_eq!(text.matches("You:").count(), 1);
+
+        // Others start at the left edge of the chat, own messages end at the right one
+        let row_of = |word: &str| (0..20).find(|y| row_text(&terminal, *y).contains(word)).unwrap();
+        let (other, own) = (row_text(&terminal, row_of("first")), row_text(&terminal, row_of("mine")));
+        let left_edge = other.find("first").unwrap();
+        assert!(own.find("mine").unwrap() > left_edge);
+        assert_eq!(other.find("first"), row_text(&terminal, row_of("second")).find("second"));
+
+        let buffer = terminal.backend().buffer();
+        let bg_at  = |word: &str| {
+            let x = row_text(&terminal, row_of(word)).chars().take_while(|c| *c != word.chars().next().unwrap()).count();
+            buffer[(x as u16, row_of(word))].bg
+        };
+        assert_eq!(bg_at("mine"), app.theme.own_bg);
+        assert_eq!(bg_at("first"), app.theme.other_bg);
+    }
+
+    #[test]
+    fn status_bar_shows_mode_room_and_notice() {
+        let runtime = Runtime::new().unwrap();
+        let (mut app, _ws) = offline_app(&runtime);
+        let mut terminal = Terminal::new(TestBackend::new(100, 20)).unwrap();
+        app.session.show_name = Some("ali".to_string());
+        app.room_names   = vec!["lobby".to_string()];
+        app.room_hashes  = vec!["0123456789abcdef".to_string()];
+        app.room_visibility = vec![Visibility::Private];
+        app.current_room = Some("0123456789abcdef".to_string());
+        app.mode = states::Modes::Insert;
+        app.notify(Severity::Success, "Room created");
+
+        terminal.draw(|f| draw_ui(f, &app, &app.theme)).unwrap();
+        let status = row_text(&terminal, 19);
+        assert!(status.contains("INSERT"));
+        assert!(status.contains("ali"));
+        assert!(status.contains("#lobby (01234567)"));
+        assert!(status.contains("Offline"));
+        assert!(buffer_text(&terminal).contains("Messages [Offline]"));
+        assert!(status.contains("127.0.0.1"));
+        let first_room = row_text(&terminal, 1);
+        assert!(first_room.contains('\u{1f512}') && first_room.contains("lobby"));
+        assert!(status.trim_end().ends_with("Room created"));
+
+        // Errors also pop up as a toast
+        app.notify(Severity::Error, "Server said no");
+        terminal.draw(|f| draw_ui(f, &app, &app.theme)).unwrap();
+        assert!(row_text(&terminal, 19).contains("Server said no"));
+        assert!(row_text(&terminal, 0).contains("Error"));
+        assert!(row_text(&terminal, 1).contains("Server said no"));
+    }
+
+    #[test]
+    fn passwords_are_masked_until_revealed() {
+        let runtime = Runtime::new().unwrap();
+        let (mut app, _ws) = offline_app(&runtime);
+        let mut terminal = Terminal::new(TestBackend::new(100, 30)).unwrap();
+        app.selected_screen = states::Screen::Form;
+        app.form = Form::new(Some(states::Forms::SignIn), None);
+        app.form.set_value(0, "ali");
+        app.form.set_value(1, "hunter2");
+
+        terminal.draw(|f| draw_ui(f, &app, &app.theme)).unwrap();
+        let text = buffer_text(&terminal);
+        assert!(text.contains("ali"));
+        assert!(!text.contains("hunter2"));
+        assert!(text.contains(&"\u{2022}".repeat(7)));
+
+        app.reveal_secrets();
+        terminal.draw(|f| draw_ui(f, &app, &app.theme)).unwrap();
+        assert!(buffer_text(&terminal).contains("hunter2"));
+    }
+
+    #[test]
+    fn form_is_drawn_from_its_spec() {
+        let runtime = Runtime::new().unwrap();
+        let (mut app, _ws) = offline_app(&runtime);
+        let mut terminal = Terminal::new(TestBackend::new(160, 40)).unwrap();
+        app.selected_screen = states::Screen::Form;
+        app.form = Form::new(Some(states::Forms::SignUp), None);
+        app.form.note = Some("Pick a name".to_string());
+
+        terminal.draw(|f| draw_ui(f, &app, &app.theme)).unwrap();
+        let text = buffer_text(&terminal);
+        for label in ["Sign Up", "Username:", "Confirm:", "Answer:", "Pick a name", "Password again"] {
+            assert!(text.contains(label), "{label} missing");
         }
+
+        // Typed values replace the placeholder
+        app.form.set_value(2, "x");
+        terminal.draw(|f| draw_ui(f, &app, &app.theme)).unwrap();
+        assert!(!buffer_text(&terminal).contains("Password again"));
+    }
+
+    #[test]
+    fn help_lists_keys_of_focused_block() {
+        let runtime = Runtime::new().unwrap();
+        let (mut app, _ws) = offline_app(&runtime);
+        let mut terminal = Terminal::new(TestBackend::new(80, 40)).unwrap();
+        app.selected_block = states::Block::Chat;
+        app.show_help = true;
+
+        terminal.draw(|f| draw_ui(f, &app, &app.theme)).unwrap();
+        let text = buffer_text(&terminal);
+        assert!(text.contains("Jump to oldest message"));
+        assert!(text.contains("Home, g g"));
+        assert!(!text.contains("Create a room"));
+    }
+
+    #[test]
+    fn cursor_counts_display_width_of_wide_chars() {
+        let runtime = Runtime::new().unwrap();
+        let (mut app, _ws) = offline_app(&runtime);
+        let mut terminal = Terminal::new(TestBackend::new(60, 20)).unwrap();
+        app.mode = states::Modes::Insert;
+
+        terminal.draw(|f| draw_ui(f, &app, &app.theme)).unwrap();
+        let empty = terminal.get_cursor_position().unwrap();
+
+        for c in "你好".chars() {
+            app.insert_char(c);
+        }
+        terminal.draw(|f| draw_ui(f, &app, &app.theme)).unwrap();
+        let wide = terminal.get_cursor_position().unwrap();
+
+        assert_eq!(wide.x, empty.x + 4);
+        assert_eq!(wide.y, empty.y);
+        assert!(buffer_text(&terminal).contains("你"));
+    }
+
+    #[test]
+    fn overflowing_input_scrolls_with_cursor() {
+        let runtime = Runtime::new().unwrap();
+        let (mut app, _ws) = offline_app(&runtime);
+        let mut terminal = Terminal::new(TestBackend::new(40, 30)).unwrap();
+        app.mode = states::Modes::Insert;
+
+        // Way more than max_input_rows rows worth of text
+        for c in "word ".repeat(40).chars().chain("last".chars()) {
+            app.insert_char(c);
+        }
+        terminal.draw(|f| draw_ui(f, &app, &app.theme)).unwrap();
+
+        let cursor = terminal.get_cursor_position().unwrap();
+        assert!(cursor.y < 30);
+        assert!(row_text(&terminal, cursor.y).contains("last"));
+
+        // Box stops growing once it's max_input_rows tall
+        let input_rows = (0..30)
+            .filter(|y| {
+                let row = row_text(&terminal, *y);
+                row.contains("word") || row.contains("last")
+            })
+            .count();
+        assert_eq!(input_rows, app.config.layout.max_input_rows as usize);
     }
 }
@@ -1 +1,4 @@
+pub mod bidi;
 pub mod core;
+pub mod theme;
+pub mod wrap;
@@ -0,0 +1,350 @@
+use crate::settings::config_dir;
+use ratatui::style::Color;
+use serde::Deserialize;
+use std::{
+    collections::BTreeMap,
+    env, fs,
+    path::PathBuf,
+    str::FromStr,
+};
+
+
+// Every color draw_ui uses, so nothing on screen is hard-coded
+#[derive(Clone, Debug, PartialEq)]
+pub struct Theme {
+    pub name:          String,
+    pub border:        Color, // Focused pane
+    pub unfocused:     Color,
+    pub typing_border: Color, // Typing box in insert mode
+    pub text:          Color, // What the user types
+    pub form:          Color,
+    pub selected_bool: Color,
+    pub highlight_fg:  Color, // Selected room or profile
+    pub highlight_bg:  Color,
+    pub own_fg:        Color,
+    pub own_bg:        Color,
+    pub other_fg:      Color,
+    pub other_bg:      Color,
+    pub error:         Color,
+    pub warning:       Color,
+    pub success:       Color,
+    pub muted:         Color, // Placeholders and timestamps
+}
+
+pub const BUILT_IN: [&str; 3] = ["dark", "light", "high-contrast"];
+
+impl Default for Theme {
+    fn default() -> Self {
+        Self::dark()
+    }
+}
+
+// [theme] in config.toml or a themes/<name>.toml file, colors lay over the named theme
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct ThemeConfig {
+    #[serde(alias = "base")]
+    pub name:   Option<String>,
+    #[serde(flatten)]
+    pub colors: BTreeMap<String, String>,
+}
+
+impl Theme {
+    fn dark() -> Self {
+        Self {
+            name:          "dark".to_string(),
+            border:        Color::Rgb(11, 255, 37),
+            unfocused:     Color::White,
+            typing_border: Color::Rgb(253, 242, 83),
+            text:          Color::Rgb(230, 230, 230),
+            form:          Color::Rgb(247, 155, 35),
+            selected_bool: Color::Rgb(94, 94, 94),
+            highlight_fg:  Color::Rgb(0, 0, 0),
+            highlight_bg:  Color::Rgb(11, 255, 37),
+            own_fg:        Color::Rgb(255, 255, 255),
+            own_bg:        Color::Rgb(24, 100, 40),
+            other_fg:      Color::Rgb(0, 0, 0),
+            other_bg:      Color::Rgb(200, 200, 200),
+            error:         Color::Rgb(255, 85, 85),
+            warning:       Color::Rgb(253, 242, 83),
+            success:       Color::Rgb(11, 255, 37),
+            muted:         Color::Rgb(128, 128, 128),
+        }
+    }
+
+    fn light() -> Self {
+        Self {
+            name:          "light".to_string(),
+            border:        Color::Rgb(0, 130, 60),
+            unfocused:     Color::Rgb(150, 150, 150),
+            typing_border: Color::Rgb(190, 120, 0),
+            text:          Color::Rgb(30, 30, 30),
+            form:          Color::Rgb(200, 100, 0),
+            selected_bool: Color::Rgb(210, 210, 210),
+            highlight_fg:  Color::Rgb(255, 255, 255),
+            highlight_bg:  Color::Rgb(0, 130, 60),
+            own_fg:        Color::Rgb(0, 0, 0),
+            own_bg:        Color::Rgb(190, 235, 200),
+            other_fg:      Color::Rgb(0, 0, 0),
+            other_bg:      Color::Rgb(225, 225, 225),
+            error:         Color::Rgb(190, 0, 0),
+            warning:       Color::Rgb(170, 110, 0),
+            success:       Color::Rgb(0, 130, 60),
+            muted:         Color::Rgb(110, 110, 110),
+        }
+    }
+
+    // Only the 16 basic colors, readable everywhere
+    fn high_contrast() -> Self {
+        Self {
+            name:          "high-contrast".to_string(),
+            border:        Color::Yellow,
+            unfocused:     Color::White,
+            typing_border: Color::Cyan,
+            text:          Color::White,
+            form:          Color::Yellow,
+            selected_bool: Color::Blue,
+            highlight_fg:  Color::Black,
+            highlight_bg:  Color::Yellow,
+            own_fg:        Color::Black,
+            own_bg:        Color::Cyan,
+            other_fg:      Color::Black,
+            other_bg:      Color::White,
+            error:         Color::LightRed,
+            warning:       Color::Yellow,
+            success:       Color::LightGreen,
+            muted:         Color::Gray,
+        }
+    }
+
+    fn built_in(name: &str) -> Option<Self> {
+        match name {
+            "dark"          => Some(Self::dark()),
+            "light"         => Some(Self::light()),
+            "high-contrast" => Some(Self::high_contrast()),
+            _               => None,
+        }
+    }
+
+    fn dir() -> PathBuf {
+        config_dir().join("themes")
+    }
+
+    // Built-in names first, then user files from the themes dir
+    pub fn available() -> Vec<String> {
+        let mut names: Vec<String> = BUILT_IN.iter().map(|n| n.to_string()).collect();
+        let mut user: Vec<String> = fs::read_dir(Self::dir())
+            .into_iter()
+            .flatten()
+            .flatten()
+            .filter_map(|entry| {
+                let path = entry.path();
+                match path.extension().and_then(|e| e.to_str()) {
+                    Some("toml") => path.file_stem().and_then(|s| s.to_str()).map(str::to_string),
+                    _            => None,
+                }
+            })
+            .filter(|name| !names.contains(name))
+            .collect();
+        user.sort();
+        names.append(&mut user);
+        names
+    }
+
+    // A built-in or user theme by name, user files can build on another theme
+    pub fn by_name(name: &str) -> Result<Self, String> {
+        Self::by_name_within(name, 0)
+    }
+
+    fn by_name_within(name: &str, depth: usize) -> Result<Self, String> {
+        if let Some(theme) = Self::built_in(name) {
+            return Ok(theme)
+        }
+        if depth > 4 {
+            return Err(format!("theme \"{name}\" builds on itself"))
+        }
+
+        let path = Self::dir().join(format!("{name}.toml"));
+        let raw  = fs::read_to_string(&path).map_err(|_| format!("no theme named \"{name}\""))?;
+        let file: ThemeConfig = toml::from_str(&raw).map_err(|e| format!("{}: {}", path.display(), e.message()))?;
+
+        let base = file.name.as_deref().unwrap_or("dark");
+        let mut theme = Self::by_name_within(base, depth + 1)?;
+        theme.apply(&file.colors)?;
+        theme.name = name.to_string();
+        Ok(theme)
+    }
+
+    pub fn from_config(config: &ThemeConfig) -> Result<Self, String> {
+        let mut theme = Self::by_name(config.name.as_deref().unwrap_or("dark"))?;
+        theme.apply(&config.colors)?;
+        Ok(theme)
+    }
+
+    fn slots(&mut self) -> [(&'static str, &mut Color); 16] {
+        [
+            ("border",        &mut self.border),
+            ("unfocused",     &mut self.unfocused),
+            ("typing_border", &mut self.typing_border),
+            ("text",          &mut self.text),
+            ("form",          &mut self.form),
+            ("selected_bool", &mut self.selected_bool),
+            ("highlight_fg",  &mut self.highlight_fg),
+            ("highlight_bg",  &mut self.highlight_bg),
+            ("own_fg",        &mut self.own_fg),
+            ("own_bg",        &mut self.own_bg),
+            ("other_fg",      &mut self.other_fg),
+            ("other_bg",      &mut self.other_bg),
+            ("error",         &mut self.error),
+            ("warning",       &mut self.warning),
+            ("success",       &mut self.success),
+            ("muted",         &mut self.muted),
+        ]
+    }
+
+    // Colors are written as names ("red"), indexes ("42") or hex ("#0bff25")
+    fn apply(&mut self, colors: &BTreeMap<String, String>) -> Result<(), String> {
+        for (key, value) in colors {
+            let color = Color::from_str(value).map_err(|_| format!("unknown color \"{value}\" for {key}"))?;
+            let mut slots = self.slots();
+            match slots.iter_mut().find(|(name, _)| name == key) {
+                Some((_, slot)) => **slot = color,
+                None            => return Err(format!("theme has no color named \"{key}\"")),
+            }
+        }
+        Ok(())
+    }
+
+    // Same theme squeezed into what the terminal can show
+    pub fn for_depth(mut self, depth: ColorDepth) -> Self {
+        for (_, slot) in self.slots() {
+            *slot = depth.fit(*slot);
+        }
+        self
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ColorDepth {
+    TrueColor,
+    Indexed256,
+    Basic16,
+}
+
+// xterm's defaults for the 16 basic colors
+const BASIC: [(Color, (u8, u8, u8)); 16] = [
+    (Color::Black,        (0, 0, 0)),
+    (Color::Red,          (205, 0, 0)),
+    (Color::Green,        (0, 205, 0)),
+    (Color::Yellow,       (205, 205, 0)),
+    (Color::Blue,         (0, 0, 238)),
+    (Color::Magenta,      (205, 0, 205)),
+    (Color::Cyan,         (0, 205, 205)),
+    (Color::Gray,         (229, 229, 229)),
+    (Color::DarkGray,     (127, 127, 127)),
+    (Color::LightRed,     (255, 0, 0)),
+    (Color::LightGreen,   (0, 255, 0)),
+    (Color::LightYellow,  (255, 255, 0)),
+    (Color::LightBlue,    (92, 92, 255)),
+    (Color::LightMagenta, (255, 0, 255)),
+    (Color::LightCyan,    (0, 255, 255)),
+    (Color::White,        (255, 255, 255)),
+];
+
+const CUBE: [u8; 6] = [0, 95, 135, 175, 215, 255];
+
+fn distance(a: (u8, u8, u8), b: (u8, u8, u8)) -> u32 {
+    let d = |x: u8, y: u8| (x as i32 - y as i32).pow(2) as u32;
+    d(a.0, b.0) + d(a.1, b.1) + d(a.2, b.2)
+}
+
+// RGB of a 256 palette entry
+fn indexed_rgb(i: u8) -> (u8, u8, u8) {
+    match i {
+        0..=15   => BASIC[i as usize].1,
+        16..=231 => {
+            let i = i - 16;
+            (CUBE[(i / 36) as usize], CUBE[(i / 6 % 6) as usize], CUBE[(i % 6) as usize])
+        },
+        _        => {
+            let v = 8 + (i - 232) * 10;
+            (v, v, v)
+        },
+    }
+}
+
+impl ColorDepth {
+    // Terminals advertise truecolor through COLORTERM, 256 colors through TERM
+    pub fn detect() -> Self {
+        let colorterm = env::var("COLORTERM").unwrap_or_default().to_lowercase();
+        let term      = env::var("TERM").unwrap_or_default();
+        if colorterm == "truecolor" || colorterm == "24bit" {
+            ColorDepth::TrueColor
+        } else if term.contains("256") {
+            ColorDepth::Indexed256
+        } else {
+            ColorDepth::Basic16
+        }
+    }
+
+    fn fit(self, color: Color) -> Color {
+        match (self, color) {
+            (ColorDepth::TrueColor, _) => color,
+            (ColorDepth::Indexed256, Color::Rgb(r, g, b)) => {
+                // Closest of the 6x6x6 cube and the grayscale ramp
+                let near  = |v: u8| CUBE.iter().enumerate().min_by_key(|(_, c)| (**c as i32 - v as i32).abs()).map(|(i, _)| i as u8).unwrap_or(0);
+                let cube  = 16 + 36 * near(r) + 6 * near(g) + near(b);
+                let level = ((r as u32 + g as u32 + b as u32) / 3) as u8;
+                let gray  = 232 + (level.saturating_sub(3) / 10).min(23);
+                let pick  = match distance(indexed_rgb(cube), (r, g, b)) <= distance(indexed_rgb(gray), (r, g, b)) {
+                    true  => cube,
+                    false => gray,
+                };
+                Color::Indexed(pick)
+            },
+            (ColorDepth::Basic16, Color::Rgb(r, g, b))  => nearest_basic((r, g, b)),
+            (ColorDepth::Basic16, Color::Indexed(i))    => nearest_basic(indexed_rgb(i)),
+            _ => color,
+        }
+    }
+}
+
+fn nearest_basic(rgb: (u8, u8, u8)) -> Color {
+    BASIC.iter()
+        .min_by_key(|(_, c)| distance(*c, rgb))
+        .map(|(color, _)| *color)
+        .unwrap_or(Color::Reset)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn built_ins_load_and_override() {
+        for name in BUILT_IN {
+            assert_eq!(Theme::by_name(name).unwrap().name, name);
+        }
+
+        let config: ThemeConfig = toml::from_str("name = \"light\"\nborder = \"#112233\"").unwrap();
+        let theme = Theme::from_config(&config).unwrap();
+        assert_eq!(theme.border, Color::Rgb(0x11, 0x22, 0x33));
+        assert_eq!(theme.own_bg, Theme::light().own_bg);
+
+        let config: ThemeConfig = toml::from_str("bordr = \"red\"").unwrap();
+        assert!(Theme::from_config(&config).is_err());
+        assert!(Theme::by_name("no-such-theme").is_err());
+    }
+
+    #[test]
+    fn falls_back_to_smaller_palettes() {
+        assert_eq!(ColorDepth::Indexed256.fit(Color::Rgb(255, 0, 0)), Color::Indexed(196));
+        assert_eq!(ColorDepth::Indexed256.fit(Color::Rgb(128, 128, 128)), Color::Indexed(244));
+        assert_eq!(ColorDepth::Basic16.fit(Color::Rgb(11, 255, 37)), Color::LightGreen);
+        assert_eq!(ColorDepth::Basic16.fit(Color::Indexed(196)), Color::LightRed);
+        assert_eq!(ColorDepth::Basic16.fit(Color::Yellow), Color::Yellow);
+
+        let mut theme = Theme::default().for_depth(ColorDepth::Basic16);
+        assert!(theme.slots().iter().all(|(_, c)| !matches!(c, Color::Rgb(..) | Color::Indexed(_))));
+    }
+}
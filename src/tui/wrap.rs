@@ -0,0 +1,142 @@
+use unicode_width::UnicodeWidthChar;
+
+
+pub fn char_width(c: char) -> usize {
+    c.width().unwrap_or(0)
+}
+
+// Splits text into rows no wider than `width` columns, as char index ranges.
+// Breaks after the last space that fits, or mid-word when a word is wider than a row.
+// Rows cover every char exactly once so cursor positions map back and forth.
+pub fn wrap_ranges(text: &str, width: usize) -> Vec<(usize, usize)> {
+    let chars: Vec<char> = text.chars().collect();
+    let width = width.max(1);
+    let mut rows = Vec::new();
+    let mut start = 0;
+
+    while start < chars.len() {
+        let mut cols = 0;
+        let mut end = start;
+        let mut last_space = None;
+
+        while end < chars.len() {
+            let w = char_width(chars[end]);
+            // A wide char that can't fit still needs a row of its own
+            if cols + w > width && end > start {
+                break
+            }
+            cols += w;
+            if chars[end].is_whitespace() {
+                last_space = Some(end);
+            }
+            end += 1;
+        }
+
+        // Don't cut words in half when there's a space to break on
+        if end < chars.len() && !chars[end].is_whitespace() {
+            if let Some(space) = last_space {
+                end = space + 1;
+            }
+        }
+        // Spaces right at the break belong to the row they end while they fit, the rest start the next one
+        let mut cols: usize = chars[start..end].iter().copied().map(char_width).sum();
+        while end < chars.len() && chars[end] == ' ' && cols < width {
+            cols += 1;
+            end += 1;
+        }
+
+        rows.push((start, end));
+        start = end;
+    }
+
+    if rows.is_empty() {
+        rows.push((0, 0));
+    }
+    rows
+}
+
+// Row and display column of the char at `index` once wrapped
+pub fn cursor_in_wrapped(text: &str, index: usize, width: usize) -> (usize, usize) {
+    let chars: Vec<char> = text.chars().collect();
+    let rows = wrap_ranges(text, width);
+    let index = index.min(chars.len());
+
+    for (row, (s, e)) in rows.iter().enumerate() {
+        if index < *e || (index == *e && row == rows.len() - 1) {
+            let col: usize = chars[*s..index].iter().copied().map(char_width).sum();
+            // Cursor sitting right after a full row goes to the next one
+            if col >= width.max(1) {
+                return (row + 1, 0)
+            }
+            return (row, col)
+        }
+    }
+    (rows.len() - 1, 0)
+}
+
+// Drops at least `cols` columns from the left, returns what's left and how many columns went
+pub fn skip_columns(text: &str, cols: usize) -> (String, usize) {
+    let mut hidden = 0;
+    let mut chars = text.chars().peekable();
+    while hidden < cols {
+        match chars.next() {
+            Some(c) => hidden += char_width(c),
+            None    => break,
+        }
+    }
+    (chars.collect(), hidden)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn wrap(text: &str, width: usize) -> Vec<String> {
+        let chars: Vec<char> = text.chars().collect();
+        wrap_ranges(text, width)
+            .into_iter()
+            .map(|(s, e)| chars[s..e].iter().collect())
+            .collect()
+    }
+
+    #[test]
+    fn wraps_on_spaces() {
+        assert_eq!(wrap("hello big world", 10), vec!["hello big ", "world"]);
+        assert_eq!(wrap("hello big world", 9), vec!["hello big", " world"]);
+        assert_eq!(wrap("hello big world", 8), vec!["hello ", "big ", "world"]);
+    }
+
+    #[test]
+    fn spaces_at_the_break_never_overflow() {
+        assert_eq!(wrap("hello     world", 8), vec!["hello   ", "  world"]);
+        assert_eq!(wrap("abc      ", 3), vec!["abc", "   ", "   "]);
+        for row in wrap("one  two   three    four", 5) {
+            assert!(row.chars().count() <= 5, "{row:?}");
+        }
+    }
+
+    #[test]
+    fn breaks_long_words() {
+        assert_eq!(wrap("abcdefgh", 3), vec!["abc", "def", "gh"]);
+    }
+
+    #[test]
+    fn counts_wide_chars_as_two_columns() {
+        assert_eq!(char_width('你'), 2);
+        assert_eq!(wrap("你好世界", 5), vec!["你好", "世界"]);
+        assert_eq!(cursor_in_wrapped("你好世界", 3, 5), (1, 2));
+    }
+
+    #[test]
+    fn cursor_after_full_row_moves_down() {
+        assert_eq!(cursor_in_wrapped("abc", 3, 3), (1, 0));
+        assert_eq!(cursor_in_wrapped("abc", 2, 3), (0, 2));
+    }
+
+    #[test]
+    fn skips_whole_wide_chars() {
+        assert_eq!(skip_columns("abcdef", 3), ("def".to_string(), 3));
+        assert_eq!(skip_columns("你好世界", 3), ("世界".to_string(), 4));
+    }
+}